@@ -0,0 +1,350 @@
+#![allow(non_snake_case)]
+
+use Smart_Road::*;
+use Smart_Road::controller;
+use Smart_Road::layout::IntersectionLayout;
+use Smart_Road::network::RoadNetwork;
+use Smart_Road::replay::{Recording, Replay};
+use Smart_Road::report::Report;
+use Smart_Road::routing::{OdDemand, OdGenerator};
+use Smart_Road::scenario::{Scenario, ScenarioPlayer};
+use Smart_Road::snapshot::Snapshot;
+use Smart_Road::traffic::{self, Demand, TrafficGenerator};
+use Smart_Road::vehicle::{VehicleKind, VehicleMix};
+
+// Usage: smart_road_batch [--seconds 60] [--spawn-interval 0.5] [--seed <n>] [--safety-distance 5]
+//                         [--headway <seconds>] [--json <path>] [--csv <path>] [--layout <file>]
+//                         [--controller <name>] [--deadlock-ticks 1800]
+//                         [--demand <file>] [--rate <cars per second>] [--scenario <file>]
+//                         [--record <file>] [--replay <file>]
+//                         [--load-state <file>] [--save-state <file>] [--mix <file>]
+//                         [--grid <columns>x<rows>] [--road-length 200]
+//                         [--od <file> | --od-rate <vehicles/s per entry>] [--reroute]
+//
+// Runs the intersection without a window: every `spawn-interval` simulated seconds
+// a car is requested on a random route, then the final statistics are printed.
+// `--json` also writes them to a file; `--csv` writes the cars to `<path>` and the
+// aggregates, collisions and close calls next to it, e.g. `out_summary.csv`.
+// With `--demand` (or `--rate`, the same rate on every approach) cars arrive
+// according to a Poisson traffic generator instead. A `--scenario` spawns exactly
+// the cars it lists, runs until they are all gone (or for its duration) and exits
+// with an error if its expectations are not met; its seed and controller are used
+// unless given on the command line. `--record` saves the run so that `--replay`
+// (or the window's `--replay`) can rerun it tick for tick. `--load-state` continues
+// from a snapshot saved with `--save-state` for another `seconds`, replacing the
+// layout, controller and safety settings with the saved ones.
+// Cars keep `safety-distance` pixels to each other, plus `headway` seconds of
+// travel to the car ahead when given. `--mix` spawns trucks, buses and emergency
+// vehicles alongside cars in the given shares.
+//
+// `--grid` simulates a road network of intersections instead: every `spawn-interval`
+// a vehicle enters at a random edge of the grid and takes random turns until it
+// drives out of it again. With `--od` (or `--od-rate`, spread evenly over all
+// entries and exits) vehicles arrive with a destination instead and take the fastest
+// way there; `--reroute` replans their way around queues at every intersection.
+
+const DEFAULT_SECONDS: f32 = 60.0;
+const DEFAULT_SPAWN_INTERVAL: f32 = 0.5;
+// Scenarios without a duration stop at the latest after this many seconds.
+const SCENARIO_TIME_LIMIT: f32 = 600.0;
+const DEFAULT_ROAD_LENGTH: f32 = 200.0;
+
+fn arg<T: std::str::FromStr>(args: &[String], name: &str) -> Option<T> {
+    let i = args.iter().position(|a| a == name)?;
+    args.get(i + 1)?.parse().ok()
+}
+
+fn vehicle_mix(args: &[String]) -> Option<VehicleMix> {
+    arg::<String>(args, "--mix").map(|path| VehicleMix::load(&path).unwrap_or_else(|e| {
+        eprintln!("failed to load vehicle mix {}: {}", path, e);
+        std::process::exit(1);
+    }))
+}
+
+/// Runs a grid of intersections with random trips and prints per-trip statistics.
+fn run_network(args: &[String], grid: &str, layout: IntersectionLayout, seed: u64, seconds: f32, spawn_interval: f32) {
+    let size: Vec<usize> = grid.split('x').filter_map(|n| n.parse().ok()).collect();
+    let (columns, rows) = match size[..] {
+        [columns, rows] if columns > 0 && rows > 0 => (columns, rows),
+        _ => {
+            eprintln!("expected --grid <columns>x<rows>, got {}", grid);
+            std::process::exit(1);
+        }
+    };
+    let controller_name = arg::<String>(args, "--controller").unwrap_or_else(|| "queue".to_string());
+    let road_length = arg(args, "--road-length").unwrap_or(DEFAULT_ROAD_LENGTH);
+    let mut network = RoadNetwork::grid(columns, rows, layout, &controller_name, seed, road_length).unwrap_or_else(|| {
+        eprintln!("unknown controller {}, expected one of {:?}", controller_name, controller::CONTROLLERS);
+        std::process::exit(1);
+    });
+    let mix = vehicle_mix(args);
+    if let Some(mix) = mix {
+        network.set_vehicle_mix(mix);
+    }
+    let od = match (arg::<String>(args, "--od"), arg::<f32>(args, "--od-rate")) {
+        (Some(path), _) => Some(OdDemand::load(&path).unwrap_or_else(|e| {
+            eprintln!("failed to load od demand {}: {}", path, e);
+            std::process::exit(1);
+        })),
+        (None, Some(rate)) => Some(OdDemand::uniform(&network, rate)),
+        (None, None) => None,
+    };
+    let mut generator = od.map(|mut demand| {
+        demand.reroute |= args.iter().any(|a| a == "--reroute");
+        OdGenerator::new(demand, seed, &network).unwrap_or_else(|e| {
+            eprintln!("od demand does not fit the grid: {}", e);
+            std::process::exit(1);
+        })
+    });
+    let mut rng = traffic::arrival_rng(seed);
+    let entries = network.entries();
+    let ticks = (seconds / TIME_STEP).round() as u64;
+    let spawn_every = ((spawn_interval / TIME_STEP).round() as u64).max(1);
+    for tick in 0..ticks {
+        if let Some(generator) = &mut generator {
+            generator.update(&mut network);
+        } else if tick % spawn_every == 0 {
+            let (mut at, direction) = entries[r::Rng::gen_range(&mut rng, 0..entries.len())];
+            let mut hops = vec![];
+            let mut routes: Vec<Route> = Route::ALL.iter().copied().filter(|r| r.get_direction() == direction).collect();
+            // random walk until the vehicle leaves the grid, never longer than crossing it twice
+            while hops.len() < 2 * (columns + rows) {
+                let route = routes[r::Rng::gen_range(&mut rng, 0..routes.len())];
+                hops.push((at, route));
+                match network.next_intersection(at, route.exit_direction()) {
+                    Some(next) => at = next,
+                    None => break,
+                }
+                routes = Route::ALL.iter().copied().filter(|r| r.get_direction() == route.exit_direction()).collect();
+            }
+            network.add_trip(hops).expect("random walk follows the roads");
+        }
+        network.step(TIME_STEP);
+    }
+
+    let trips = network.finished_trips();
+    println!("seed: {}", seed);
+    println!("controller: {}", controller_name);
+    println!("grid: {}x{}", columns, rows);
+    println!("simulated seconds: {:.2}", network.time());
+    println!("finished_trips: {}", trips.len());
+    println!("active_trips: {}", network.active_trips().count());
+    if let Some(generator) = &generator {
+        println!("generated_trips: {}", generator.generated);
+        println!("unroutable_trips: {}", generator.unroutable);
+        println!("rerouted_trips: {}", trips.iter().chain(network.active_trips()).filter(|t| t.rerouted > 0).count());
+    }
+    if !trips.is_empty() {
+        let times: Vec<f32> = trips.iter().map(|t| t.arrived_at.unwrap() - t.departed_at).collect();
+        let hops: usize = trips.iter().map(|t| t.hops.len()).sum();
+        println!("trip_time_mean: {:.3}", times.iter().sum::<f32>() / times.len() as f32);
+        println!("trip_time_max: {:.3}", times.iter().cloned().fold(0.0, f32::max));
+        println!("hops_mean: {:.2}", hops as f32 / trips.len() as f32);
+    }
+    if mix.is_some() {
+        for kind in VehicleKind::ALL {
+            let times: Vec<f32> = trips.iter()
+                .filter(|t| t.kind == Some(kind))
+                .map(|t| t.arrived_at.unwrap() - t.departed_at)
+                .collect();
+            if !times.is_empty() {
+                println!("{:?}: trips {}, trip_time_mean {:.3}", kind, times.len(),
+                         times.iter().sum::<f32>() / times.len() as f32);
+            }
+        }
+    }
+    for (i, intersection) in network.intersections().iter().enumerate() {
+        println!("intersection {}: passed {}, collapse {}, close_calls {}, deadlocks {}", i,
+                 intersection.number_of_passed_vehicles, intersection.collapse,
+                 intersection.close_calls, intersection.deadlocks().len());
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let scenario = arg::<String>(&args, "--scenario").map(|path| Scenario::load(&path).unwrap_or_else(|e| {
+        eprintln!("failed to load scenario {}: {}", path, e);
+        std::process::exit(1);
+    }));
+    let seconds: f32 = match &scenario {
+        Some(scenario) => arg(&args, "--seconds").or(scenario.duration).unwrap_or(SCENARIO_TIME_LIMIT),
+        None => arg(&args, "--seconds").unwrap_or(DEFAULT_SECONDS),
+    };
+    let spawn_interval: f32 = arg(&args, "--spawn-interval").unwrap_or(DEFAULT_SPAWN_INTERVAL);
+    let layout = match arg::<String>(&args, "--layout") {
+        Some(path) => IntersectionLayout::load(&path).unwrap_or_else(|e| {
+            eprintln!("failed to load layout {}: {}", path, e);
+            std::process::exit(1);
+        }),
+        None => IntersectionLayout::default(),
+    };
+    let seed = arg(&args, "--seed")
+        .or(scenario.as_ref().and_then(|s| s.seed))
+        .unwrap_or_else(r::random);
+    if let Some(grid) = arg::<String>(&args, "--grid") {
+        run_network(&args, &grid, layout, seed, seconds, spawn_interval);
+        return;
+    }
+    let mut intersection = Intersection::with_layout(seed, layout);
+    let controller_name = arg::<String>(&args, "--controller")
+        .or(scenario.as_ref().and_then(|s| s.controller.clone()));
+    if let Some(name) = controller_name {
+        match controller::by_name(&name, intersection.layout()) {
+            Some(controller) => intersection.set_controller(controller),
+            None => {
+                eprintln!("unknown controller {}, expected one of {:?}", name, controller::CONTROLLERS);
+                std::process::exit(1);
+            }
+        }
+    }
+    let min_gap = arg(&args, "--safety-distance").unwrap_or(SAFETY_DISTANCE);
+    intersection.safe_distance = match arg(&args, "--headway") {
+        Some(headway) => SafeDistance::Headway { min_gap, headway },
+        None => SafeDistance::Fixed(min_gap),
+    };
+
+    if let Some(deadlock_ticks) = arg(&args, "--deadlock-ticks") {
+        intersection.deadlock_ticks = deadlock_ticks;
+    }
+    if let Some(mix) = vehicle_mix(&args) {
+        intersection.vehicle_mix = mix;
+    }
+
+    let demand = match (arg::<String>(&args, "--demand"), arg::<f32>(&args, "--rate")) {
+        (Some(path), _) => Some(Demand::load(&path).unwrap_or_else(|e| {
+            eprintln!("failed to load demand {}: {}", path, e);
+            std::process::exit(1);
+        })),
+        (None, Some(rate)) => {
+            let default = Demand::default();
+            Some(default.scaled(rate / default.north.rate))
+        }
+        (None, None) => None,
+    };
+    if let Some(path) = arg::<String>(&args, "--load-state") {
+        intersection = Snapshot::load(&path).and_then(Snapshot::restore).unwrap_or_else(|e| {
+            eprintln!("failed to load state {}: {}", path, e);
+            std::process::exit(1);
+        });
+    }
+
+    let mut generator = demand.map(|d| TrafficGenerator::new(d, seed));
+    let mut player = scenario.map(ScenarioPlayer::new);
+
+    let ticks = (seconds / TIME_STEP).round() as u64;
+    let spawn_every = ((spawn_interval / TIME_STEP).round() as u64).max(1);
+    match arg::<String>(&args, "--replay") {
+        // everything is taken from the recording instead
+        Some(path) => {
+            let mut replay = Recording::load(&path).and_then(Replay::new).unwrap_or_else(|e| {
+                eprintln!("failed to load recording {}: {}", path, e);
+                std::process::exit(1);
+            });
+            while !replay.finished() {
+                replay.step();
+            }
+            intersection = replay.intersection().clone();
+            generator = None;
+            player = None;
+        }
+        None => {
+            for tick in 0..ticks {
+                if let Some(player) = player.as_mut() {
+                    player.update(&mut intersection);
+                    if player.finished(&intersection) {
+                        break;
+                    }
+                } else if let Some(generator) = generator.as_mut() {
+                    generator.update(&mut intersection);
+                } else if tick % spawn_every == 0 {
+                    intersection.add_car(Route::ALL.to_vec());
+                }
+                intersection.step(TIME_STEP);
+            }
+        }
+    }
+
+    println!("seed: {}", intersection.seed());
+    println!("controller: {}", intersection.controller().name());
+    for (name, value) in intersection.controller().summary() {
+        println!("{}: {}", name, value);
+    }
+    println!("simulated seconds: {:.2}", intersection.time());
+    if let Some(generator) = &generator {
+        println!("generated_vehicles: {}", generator.generated);
+        println!("pending_vehicles: {}", generator.pending());
+    }
+    println!("number_of_passed_vehicles: {}", intersection.number_of_passed_vehicles);
+    println!("max_velocity: {}", intersection.max_velocity);
+    if let Some(min) = intersection.min_velocity {
+        println!("min_velocity: {}", min);
+    }
+    println!("collapse: {}", intersection.collapse);
+    println!("close_calls: {}", intersection.close_calls);
+    println!("deadlocks: {}", intersection.deadlocks().len());
+    for deadlock in intersection.deadlocks() {
+        println!("  deadlock at {:.2}s, waiting {:?}, forced {:?}", deadlock.time, deadlock.cars, deadlock.forced);
+    }
+    if let Some(times) = intersection.travel_time_stats() {
+        println!("travel_time_min: {:.3}", times.min);
+        println!("travel_time_max: {:.3}", times.max);
+        println!("travel_time_mean: {:.3}", times.mean);
+        for p in [50.0, 95.0] {
+            println!("travel_time_p{}: {:.3}", p, intersection.travel_time_percentile(p).unwrap());
+        }
+    }
+    if let Some(times) = intersection.crossing_time_stats() {
+        println!("crossing_time_min: {:.3}", times.min);
+        println!("crossing_time_max: {:.3}", times.max);
+        println!("crossing_time_mean: {:.3}", times.mean);
+        for p in [50.0, 95.0] {
+            println!("crossing_time_p{}: {:.3}", p, intersection.crossing_time_percentile(p).unwrap());
+        }
+    }
+    if intersection.vehicle_mix != VehicleMix::default() {
+        for kind in VehicleKind::ALL {
+            let cars: Vec<&CarTiming> = intersection.finished_cars().iter().filter(|t| t.kind == kind).collect();
+            let crossing: Vec<f32> = cars.iter().filter_map(|t| t.crossing_time()).collect();
+            if !crossing.is_empty() {
+                println!("{:?}: passed {}, travel_time_mean {:.3}, crossing_time_mean {:.3}", kind, cars.len(),
+                         cars.iter().map(|t| t.travel_time()).sum::<f32>() / cars.len() as f32,
+                         crossing.iter().sum::<f32>() / crossing.len() as f32);
+            }
+        }
+    }
+
+    if let Some(path) = arg::<String>(&args, "--save-state") {
+        if let Err(e) = intersection.snapshot().save(&path) {
+            eprintln!("failed to write {}: {}", path, e);
+        }
+    }
+    if let Some(path) = arg::<String>(&args, "--record") {
+        if let Err(e) = intersection.recording().save(&path) {
+            eprintln!("failed to write {}: {}", path, e);
+        }
+    }
+
+    let report = Report::new(&intersection);
+    if let Some(path) = arg::<String>(&args, "--json") {
+        if let Err(e) = report.write_json(&path) {
+            eprintln!("failed to write {}: {}", path, e);
+        }
+    }
+    if let Some(path) = arg::<String>(&args, "--csv") {
+        if let Err(e) = report.write_csv(&path) {
+            eprintln!("failed to write {}: {}", path, e);
+        }
+    }
+
+    if let Some(player) = &player {
+        let failures = player.scenario().check(&intersection);
+        if failures.is_empty() {
+            println!("scenario: ok");
+        } else {
+            for failure in &failures {
+                println!("scenario failed: {}", failure);
+            }
+            std::process::exit(1);
+        }
+    }
+}
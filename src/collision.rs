@@ -0,0 +1,135 @@
+use macroquad::math::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::{snapshot, Car};
+
+/// Oriented bounding box: a rectangle of half extents `half` rotated by `heading` around `centre`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Obb {
+    pub centre: Vec2,
+    pub half: Vec2,
+    pub heading: f32,
+}
+
+/// Two cars touching or overlapping, recorded when it starts.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct CollisionEvent {
+    pub cars: (u32, u32),
+    /// Midpoint between the two car centres.
+    #[serde(with = "snapshot::vec2")]
+    pub position: Vec2,
+    pub time: f32,
+}
+
+/// Two cars closer than the safe distance, recorded when it starts.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct CloseCall {
+    pub cars: (u32, u32),
+    pub gap: f32,
+    pub time: f32,
+}
+
+impl Obb {
+    pub fn new(centre: Vec2, size: (f32, f32), heading: f32) -> Obb {
+        Obb { centre, half: vec2(size.0, size.1) / 2.0, heading }
+    }
+
+    pub fn of(car: &Car) -> Obb {
+        Obb::new(car.position, car.rectangle, car.heading)
+    }
+
+    /// The same box grown by `padding` on every side.
+    pub fn padded(&self, padding: f32) -> Obb {
+        Obb { half: self.half + vec2(padding, padding), ..*self }
+    }
+
+    fn axes(&self) -> [Vec2; 2] {
+        let (sin, cos) = self.heading.sin_cos();
+        [vec2(cos, sin), vec2(-sin, cos)]
+    }
+
+    /// Half length of the box projected onto the unit vector `axis`.
+    fn radius(&self, axis: Vec2) -> f32 {
+        let [along, across] = self.axes();
+        self.half.x * along.dot(axis).abs() + self.half.y * across.dot(axis).abs()
+    }
+
+    /// Largest gap between the boxes along any of their edge normals, negative when they
+    /// overlap. By the separating axis theorem the boxes are apart exactly when it is
+    /// positive; the value never exceeds the true distance.
+    pub fn separation(&self, other: &Obb) -> f32 {
+        let offset = other.centre - self.centre;
+        self.axes().iter()
+            .chain(other.axes().iter())
+            .map(|axis| offset.dot(*axis).abs() - self.radius(*axis) - other.radius(*axis))
+            .fold(f32::NEG_INFINITY, f32::max)
+    }
+
+    pub fn overlaps(&self, other: &Obb) -> bool {
+        self.separation(other) <= 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_4;
+
+    use super::*;
+
+    fn car_box(x: f32, y: f32, heading: f32) -> Obb {
+        Obb::new(vec2(x, y), (30.0, 10.0), heading)
+    }
+
+    #[test]
+    fn identical_boxes_overlap() {
+        let a = car_box(100.0, 100.0, 0.0);
+        assert!(a.overlaps(&a));
+    }
+
+    #[test]
+    fn boxes_in_adjacent_lanes_do_not_overlap() {
+        let a = car_box(100.0, 100.0, 0.0);
+        let b = car_box(100.0, 130.0, 0.0);
+        assert!(!a.overlaps(&b));
+        assert!((a.separation(&b) - 20.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn bumper_to_bumper_overlap() {
+        let a = car_box(100.0, 100.0, 0.0);
+        let b = car_box(125.0, 100.0, 0.0);
+        assert!(a.overlaps(&b));
+        assert!((a.separation(&b) + 5.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn crossing_at_right_angles_overlap() {
+        let a = car_box(100.0, 100.0, 0.0);
+        let b = car_box(110.0, 100.0, FRAC_PI_4 * 2.0);
+        assert!(a.overlaps(&b));
+    }
+
+    #[test]
+    fn rotated_box_clear_of_corner() {
+        // axis-aligned bounding boxes of these two overlap, the cars themselves do not
+        let a = car_box(100.0, 100.0, FRAC_PI_4);
+        let b = Obb::new(vec2(118.0, 82.0), (10.0, 10.0), 0.0);
+        assert!(!a.overlaps(&b));
+        assert!(a.separation(&b) > 0.0);
+    }
+
+    #[test]
+    fn rotated_boxes_overlap_on_the_diagonal() {
+        let a = car_box(100.0, 100.0, FRAC_PI_4);
+        let b = car_box(115.0, 115.0, FRAC_PI_4);
+        assert!(a.overlaps(&b));
+    }
+
+    #[test]
+    fn padding_makes_near_boxes_overlap() {
+        let a = car_box(100.0, 100.0, 0.0);
+        let b = car_box(133.0, 100.0, 0.0);
+        assert!(!a.overlaps(&b));
+        assert!(a.padded(5.0).overlaps(&b));
+    }
+}
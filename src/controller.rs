@@ -0,0 +1,207 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Debug;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::layout::IntersectionLayout;
+use crate::reservation::ReservationManager;
+use crate::traffic_light::TrafficLight;
+use crate::{Car, Route, CAR_SPEED_FAST, CAR_SPEED_NORMAL, CAR_SPEED_SLOW};
+
+/// What a controller may look at when deciding for one car.
+pub struct Context<'a> {
+    pub layout: &'a IntersectionLayout,
+    /// Simulation time in seconds.
+    pub time: f32,
+    pub cars: &'a HashMap<u32, Car>,
+    /// The car right ahead on the same track, if any.
+    pub leader: Option<&'a Car>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Signal {
+    Green,
+    Yellow,
+    Red,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Decision {
+    /// Speed in pixels per second the car should ramp towards.
+    pub target_speed: f32,
+    /// Whether the car may drive past the stop line into the crossing.
+    pub cleared: bool,
+}
+
+/// Right-of-way strategy of an intersection. `tick` is called once per tick, then `decide`
+/// once per car in track order; the intersection applies the decision and moves the car.
+pub trait Controller: Debug {
+    fn name(&self) -> &'static str;
+
+    fn decide(&mut self, car: &Car, context: &Context) -> Decision;
+
+    /// Called once per tick before any car is decided, also when there are no cars;
+    /// `context` has no leader.
+    fn tick(&mut self, _context: &Context) {}
+
+    /// Called once a car has driven away.
+    fn car_left(&mut self, _car_id: u32) {}
+
+    /// Called when the intersection lets a car through to break a deadlock,
+    /// so the controller can treat it as cleared from now on.
+    fn force_grant(&mut self, _car_id: u32) {}
+
+    /// Light shown to `route`, for controllers that drive signals.
+    fn signal(&self, _route: Route) -> Option<Signal> {
+        None
+    }
+
+    /// Internal state to save with a `Snapshot`.
+    fn state(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// Takes back the state saved by `state`.
+    fn restore(&mut self, _state: serde_json::Value) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Controller specific statistics as `(name, value)` pairs.
+    fn summary(&self) -> Vec<(&'static str, String)> {
+        vec![]
+    }
+
+    fn box_clone(&self) -> Box<dyn Controller>;
+}
+
+impl Clone for Box<dyn Controller> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+pub const CONTROLLERS: &[&str] = &["queue", "reservation", "traffic_light", "actuated_light"];
+
+/// Builds one of the `CONTROLLERS` by name.
+pub fn by_name(name: &str, layout: &IntersectionLayout) -> Option<Box<dyn Controller>> {
+    match name {
+        "queue" => Some(Box::new(QueueController::new())),
+        "reservation" => Some(Box::new(ReservationManager::new(layout))),
+        "traffic_light" => Some(Box::new(TrafficLight::fixed())),
+        "actuated_light" => Some(Box::new(TrafficLight::actuated())),
+        _ => None,
+    }
+}
+
+/// Slows down a car whose leader is slow, otherwise lets it drive at normal speed.
+pub fn follow(leader: Option<&Car>) -> f32 {
+    if leader.is_some_and(|c| c.is_slow_down()) {
+        CAR_SPEED_SLOW
+    } else {
+        CAR_SPEED_NORMAL
+    }
+}
+
+/// The original right-of-way logic: a car may cross when none of the routes in
+/// `Route::not_allowed_to_go` has a cleared car on the crossing; cars that had to
+/// wait are served first come, first served.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueueController {
+    occupied_tracks: HashMap<Route, HashSet<u32>>,
+    queue: VecDeque<u32>,
+    cleared: HashSet<u32>,
+}
+
+impl QueueController {
+    pub fn new() -> QueueController {
+        QueueController::default()
+    }
+
+    fn can_go(&self, car: &Car) -> bool {
+        let route = car.route;
+        let mut can_go = route.not_allowed_to_go().is_empty() || self.queue.is_empty() || self.queue[0] == car.id;
+        route.not_allowed_to_go().iter().for_each(|r| {
+            let not_speed_up = match self.occupied_tracks.get(r) {
+                Some(a) => a.iter().all(|f| !self.cleared.contains(f)),
+                None => true,
+            };
+            can_go = can_go && not_speed_up;
+        });
+        can_go
+    }
+
+    /// Clears the car if it may go, otherwise queues it; returns its new speed.
+    fn arbitrate(&mut self, car: &Car) -> f32 {
+        if self.can_go(car) {
+            self.cleared.insert(car.id);
+            if !self.queue.is_empty() && self.queue[0] == car.id {
+                self.queue.pop_front();
+            }
+            CAR_SPEED_FAST
+        } else {
+            if !self.queue.contains(&car.id) {
+                self.queue.push_back(car.id);
+            }
+            CAR_SPEED_SLOW
+        }
+    }
+}
+
+impl Controller for QueueController {
+    fn name(&self) -> &'static str {
+        "queue"
+    }
+
+    fn decide(&mut self, car: &Car, context: &Context) -> Decision {
+        let layout = context.layout;
+        let route = car.route;
+        let mut speed = car.target_speed;
+
+        if let Some(cars_on_cross_road) = self.occupied_tracks.get(&route) {
+            let mut all_cars = cars_on_cross_road.clone();
+            if !car.before_cross_road(layout) && !self.cleared.contains(&car.id) {
+                speed = self.arbitrate(car);
+                all_cars.insert(car.id);
+            } else if car.after_cross_road() {
+                all_cars.remove(&car.id);
+            }
+            if all_cars.is_empty() {
+                self.occupied_tracks.remove(&route);
+            } else {
+                self.occupied_tracks.insert(route, all_cars);
+            }
+        } else if car.on_cross_road(layout) {
+            speed = self.arbitrate(car);
+            self.occupied_tracks.insert(route, HashSet::from_iter(vec![car.id]));
+        }
+        if car.before_cross_road(layout) && context.leader.is_some() {
+            speed = follow(context.leader);
+        }
+
+        Decision { target_speed: speed, cleared: self.cleared.contains(&car.id) }
+    }
+
+    fn car_left(&mut self, car_id: u32) {
+        self.cleared.remove(&car_id);
+        self.queue.retain(|id| *id != car_id);
+    }
+
+    fn force_grant(&mut self, car_id: u32) {
+        self.cleared.insert(car_id);
+        self.queue.retain(|id| *id != car_id);
+    }
+
+    fn state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("queue state is serializable")
+    }
+
+    fn restore(&mut self, state: serde_json::Value) -> io::Result<()> {
+        *self = serde_json::from_value(state)?;
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
@@ -1,58 +1,72 @@
 use macroquad::prelude::*;
+use Smart_Road::{Direction, Intersection, Route, CAR_HEIGHT};
+use Smart_Road::controller::{Controller, Signal};
+use Smart_Road::layout::IntersectionLayout;
+use Smart_Road::vehicle::VehicleKind;
 
-/*ROAD*/
-pub fn road() {
+pub const COLORS: &[Color] = &[LIME, RED, SKYBLUE, VIOLET, GREEN, GRAY, MAROON, MAGENTA];
 
-    {
+/*ROAD*/
+pub fn road(layout: &IntersectionLayout, controller: &dyn Controller) {
+    let lanes = 2 * layout.lanes_per_approach;
+    let box_end_x = layout.box_x + layout.box_size();
+    let box_end_y = layout.box_y + layout.box_size();
+    for i in 0..=lanes {
+        // outer edges and the line between opposite directions are red, lane dividers gray
+        let color = if i % layout.lanes_per_approach == 0 { RED } else { GRAY };
+        let x = layout.box_x + i as f32 * layout.lane_width;
+        let y = layout.box_y + i as f32 * layout.lane_width;
         // center -> up
-        //first batch
-        draw_line(300.0, 0.0, 300.0, 300.0, 1.0, RED);
-        draw_line(330.0, 0.0, 330.0, 300.0, 1.0, GRAY);
-        draw_line(360.0, 0.0, 360.0, 300.0, 1.0, GRAY);
-        draw_line(390.0, 0.0, 390.0, 300.0, 1.0, RED);
-        //second batch
-        draw_line(390.0, 0.0, 390.0, 300.0, 1.0, RED);
-        draw_line(420.0, 0.0, 420.0, 300.0, 1.0, GRAY);
-        draw_line(450.0, 0.0, 450.0, 300.0, 1.0, GRAY);
-        draw_line(480.0, 0.0, 480.0, 300.0, 1.0, RED);
-    }
-    {
+        draw_line(x, 0.0, x, layout.box_y, 1.0, color);
         // center -> down
-        //first batch
-        draw_line(300.0, 480.0, 300.0, screen_height(), 1.0, RED);
-        draw_line(330.0, 480.0, 330.0, screen_height(), 1.0, GRAY);
-        draw_line(360.0, 480.0, 360.0, screen_height(), 1.0, GRAY);
-        draw_line(390.0, 480.0, 390.0, screen_height(), 1.0, RED);
-        //second batch
-        draw_line(390.0, 480.0, 390.0, screen_height(), 1.0, RED);
-        draw_line(420.0, 480.0, 420.0, screen_height(), 1.0, GRAY);
-        draw_line(450.0, 480.0, 450.0, screen_height(), 1.0, GRAY);
-        draw_line(480.0, 480.0, 480.0, screen_height(), 1.0, RED);
-    }
-    {
+        draw_line(x, box_end_y, x, layout.canvas_height, 1.0, color);
         // center -> left
-        //first batch
-        draw_line(0.0, 300.0, 300.0, 300.0, 1.0, RED);
-        draw_line(0.0, 330.0, 300.0, 330.0, 1.0, GRAY);
-        draw_line(0.0, 360.0, 300.0, 360.0, 1.0, GRAY);
-        draw_line(0.0, 390.0, 300.0, 390.0, 1.0, RED);
-        //second batch
-        draw_line(0.0, 390.0, 300.0, 390.0, 1.0, RED);
-        draw_line(0.0, 420.0, 300.0, 420.0, 1.0, GRAY);
-        draw_line(0.0, 450.0, 300.0, 450.0, 1.0, GRAY);
-        draw_line(0.0, 480.0, 300.0, 480.0, 1.0, RED);
-    }
-    {
+        draw_line(0.0, y, layout.box_x, y, 1.0, color);
         // center -> right
-        //first batch
-        draw_line(480.0, 300.0, 800.0, 300.0, 1.0, RED);
-        draw_line(480.0, 330.0, 800.0, 330.0, 1.0, GRAY);
-        draw_line(480.0, 360.0, 800.0, 360.0, 1.0, GRAY);
-        draw_line(480.0, 390.0, 800.0, 390.0, 1.0, RED);
-        //second batch
-        draw_line(480.0, 390.0, 800.0, 390.0, 1.0, RED);
-        draw_line(480.0, 420.0, 800.0, 420.0, 1.0, GRAY);
-        draw_line(480.0, 450.0, 800.0, 450.0, 1.0, GRAY);
-        draw_line(480.0, 480.0, 800.0, 480.0, 1.0, RED);
+        draw_line(box_end_x, y, layout.canvas_width, y, 1.0, color);
+    }
+    signals(layout, controller);
+}
+
+/// Traffic lights at the stop line of every approach lane, if the controller has any.
+fn signals(layout: &IntersectionLayout, controller: &dyn Controller) {
+    let thickness = 4.0;
+    let box_end_x = layout.box_x + layout.box_size();
+    let box_end_y = layout.box_y + layout.box_size();
+    for route in Route::ALL {
+        let color = match controller.signal(route) {
+            Some(Signal::Green) => GREEN,
+            Some(Signal::Yellow) => YELLOW,
+            Some(Signal::Red) => RED,
+            None => continue,
+        };
+        let direction = route.get_direction();
+        let lane = layout.lane_coordinate(direction, layout.lane_index(route)) - (layout.lane_width - CAR_HEIGHT) / 2.0;
+        match direction {
+            Direction::Down => draw_rectangle(lane, layout.box_y - thickness, layout.lane_width, thickness, color),
+            Direction::Up => draw_rectangle(lane, box_end_y, layout.lane_width, thickness, color),
+            Direction::Right => draw_rectangle(layout.box_x - thickness, lane, thickness, layout.lane_width, color),
+            Direction::Left => draw_rectangle(box_end_x, lane, thickness, layout.lane_width, color),
+        }
+    }
+}
+
+/*CARS*/
+pub fn cars(intersection: &Intersection) {
+    for car in intersection.cars() {
+        let color = match car.kind {
+            VehicleKind::Car => COLORS[car.color % COLORS.len()],
+            VehicleKind::Truck => BROWN,
+            VehicleKind::Bus => ORANGE,
+            VehicleKind::Emergency => WHITE,
+        };
+        let [a, b, c, d] = car.corners();
+        draw_triangle(a, b, c, color);
+        draw_triangle(a, c, d, color);
+        if car.kind == VehicleKind::Emergency {
+            // flashing light on the roof
+            let light = if (intersection.time() * 2.0).fract() < 0.5 { RED } else { BLUE };
+            draw_circle(car.position.x, car.position.y, car.rectangle.1 / 3.0, light);
+        }
     }
-}
\ No newline at end of file
+}
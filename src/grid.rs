@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use macroquad::math::Vec2;
+
+/// Side of one grid cell in pixels, about two car lengths.
+const CELL_SIZE: f32 = 60.0;
+
+type Cells = ((i32, i32), (i32, i32));
+
+/// Uniform grid over the canvas remembering which cars cover which cells, so
+/// neighbour queries only look at cars close by instead of every car.
+#[derive(Debug, Clone, Default)]
+pub struct SpatialGrid {
+    cells: HashMap<(i32, i32), Vec<u32>>,
+    // Cell range each car was last inserted with.
+    covered: HashMap<u32, Cells>,
+}
+
+impl SpatialGrid {
+    pub fn new() -> SpatialGrid {
+        SpatialGrid::default()
+    }
+
+    fn cell_range(min: Vec2, max: Vec2) -> Cells {
+        let cell = |v: f32| (v / CELL_SIZE).floor() as i32;
+        ((cell(min.x), cell(min.y)), (cell(max.x), cell(max.y)))
+    }
+
+    fn cells_in((min, max): Cells) -> impl Iterator<Item = (i32, i32)> {
+        (min.0..=max.0).flat_map(move |x| (min.1..=max.1).map(move |y| (x, y)))
+    }
+
+    /// Inserts a car or moves it to the cells covered by the box `min`..`max`.
+    pub fn update(&mut self, id: u32, min: Vec2, max: Vec2) {
+        let range = SpatialGrid::cell_range(min, max);
+        match self.covered.get(&id) {
+            Some(old) if *old == range => return,
+            Some(_) => self.remove(id),
+            None => {}
+        }
+        for cell in SpatialGrid::cells_in(range) {
+            self.cells.entry(cell).or_default().push(id);
+        }
+        self.covered.insert(id, range);
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        if let Some(range) = self.covered.remove(&id) {
+            for cell in SpatialGrid::cells_in(range) {
+                if let Some(ids) = self.cells.get_mut(&cell) {
+                    ids.retain(|i| *i != id);
+                    if ids.is_empty() {
+                        self.cells.remove(&cell);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Cars in the cells touched by the box `min`..`max`, each once, in no particular order.
+    pub fn near(&self, min: Vec2, max: Vec2) -> Vec<u32> {
+        let mut ids: Vec<u32> = SpatialGrid::cells_in(SpatialGrid::cell_range(min, max))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::math::vec2;
+    use r::rngs::StdRng;
+    use r::{Rng, SeedableRng};
+
+    fn overlap((a_min, a_max): (Vec2, Vec2), (b_min, b_max): (Vec2, Vec2)) -> bool {
+        a_min.x <= b_max.x && b_min.x <= a_max.x && a_min.y <= b_max.y && b_min.y <= a_max.y
+    }
+
+    #[test]
+    fn moving_across_a_cell_boundary() {
+        let mut grid = SpatialGrid::new();
+        grid.update(1, vec2(40.0, 10.0), vec2(55.0, 20.0));
+        assert_eq!(grid.near(vec2(0.0, 0.0), vec2(59.0, 59.0)), vec![1]);
+        assert!(grid.near(vec2(60.0, 0.0), vec2(119.0, 59.0)).is_empty());
+
+        // straddling the boundary it is in both cells
+        grid.update(1, vec2(50.0, 10.0), vec2(65.0, 20.0));
+        assert_eq!(grid.near(vec2(0.0, 0.0), vec2(59.0, 59.0)), vec![1]);
+        assert_eq!(grid.near(vec2(60.0, 0.0), vec2(119.0, 59.0)), vec![1]);
+
+        // and once past it, only in the new one
+        grid.update(1, vec2(61.0, 10.0), vec2(76.0, 20.0));
+        assert!(grid.near(vec2(0.0, 0.0), vec2(59.0, 59.0)).is_empty());
+        assert_eq!(grid.near(vec2(60.0, 0.0), vec2(119.0, 59.0)), vec![1]);
+
+        grid.remove(1);
+        assert!(grid.near(vec2(-100.0, -100.0), vec2(200.0, 200.0)).is_empty());
+        assert!(grid.cells.is_empty());
+    }
+
+    #[test]
+    fn near_finds_every_overlapping_pair() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut grid = SpatialGrid::new();
+        let mut boxes = vec![];
+        for round in 0..20 {
+            // move every box somewhere else, some of them off the canvas
+            boxes = (0..40).map(|_| {
+                let min = vec2(rng.gen_range(-50.0..800.0), rng.gen_range(-50.0..800.0));
+                let size = vec2(rng.gen_range(5.0..90.0), rng.gen_range(5.0..90.0));
+                (min, min + size)
+            }).collect();
+            for (id, (min, max)) in boxes.iter().enumerate() {
+                grid.update(id as u32, *min, *max);
+            }
+            if round % 2 == 1 {
+                grid.remove(0);
+                boxes[0] = (vec2(-1e4, -1e4), vec2(-1e4, -1e4));
+            }
+            for (i, a) in boxes.iter().enumerate() {
+                let near = grid.near(a.0, a.1);
+                for (j, b) in boxes.iter().enumerate() {
+                    if i != j && overlap(*a, *b) {
+                        assert!(near.contains(&(j as u32)), "round {}: {} overlaps {} but is not near", round, j, i);
+                    }
+                }
+                if round % 2 == 1 {
+                    assert!(!near.contains(&0));
+                }
+            }
+        }
+    }
+}
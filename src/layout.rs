@@ -0,0 +1,166 @@
+use std::fs;
+use std::io;
+
+use macroquad::math::vec2;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::path::Path;
+use crate::vehicle::VehicleKind;
+use crate::{Direction, Route, Turning, CAR_HEIGHT, CAR_LENGTH};
+
+/// Reads a `.json` file, or TOML for any other extension.
+pub(crate) fn read_config<T: DeserializeOwned, P: AsRef<std::path::Path>>(path: P) -> io::Result<T> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    if path.extension().is_some_and(|e| e == "json") {
+        Ok(serde_json::from_str(&text)?)
+    } else {
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Geometry of a four-way crossing.
+///
+/// The crossing box starts at (`box_x`, `box_y`) and is `2 * lanes_per_approach`
+/// lanes wide in both directions; every arm runs from the box to the canvas edge.
+/// Inside an approach lane 0 is the curb lane used by right turns and the last
+/// lane is used by left turns. Every route needs a lane of its own, so there are at
+/// least three lanes per approach.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntersectionLayout {
+    pub canvas_width: f32,
+    pub canvas_height: f32,
+    pub lane_width: f32,
+    pub lanes_per_approach: u32,
+    pub box_x: f32,
+    pub box_y: f32,
+    /// Distance before the box in which cars are managed by the intersection.
+    pub approach_zone: f32,
+}
+
+impl Default for IntersectionLayout {
+    fn default() -> Self {
+        IntersectionLayout {
+            canvas_width: 800.0,
+            canvas_height: 800.0,
+            lane_width: 30.0,
+            lanes_per_approach: 3,
+            box_x: 300.0,
+            box_y: 300.0,
+            approach_zone: 100.0,
+        }
+    }
+}
+
+impl IntersectionLayout {
+    /// Loads a layout, rejecting one that fails `validate`.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> io::Result<IntersectionLayout> {
+        let layout: IntersectionLayout = read_config(path)?;
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        // cars only keep their distance to cars on the same route, so routes must not share a lane
+        let problem = if self.lanes_per_approach < 3 {
+            Some("lanes_per_approach must be at least 3, one lane per turning")
+        } else if self.lane_width < VehicleKind::max_width() {
+            Some("lane_width is narrower than the widest vehicle")
+        } else if self.box_x < 0.0 || self.box_x + self.box_size() > self.canvas_width
+            || self.box_y < 0.0 || self.box_y + self.box_size() > self.canvas_height {
+            Some("intersection box does not fit on the canvas")
+        } else if self.approach_zone < 0.0 {
+            Some("approach_zone must not be negative")
+        } else {
+            None
+        };
+        match problem {
+            Some(msg) => Err(io::Error::new(io::ErrorKind::InvalidData, msg)),
+            None => Ok(()),
+        }
+    }
+
+    /// Side of the square crossing box.
+    pub fn box_size(&self) -> f32 {
+        2.0 * self.lanes_per_approach as f32 * self.lane_width
+    }
+
+    /// Start and end of the box along the axis a car moving in `direction` travels on.
+    pub fn box_range(&self, direction: Direction) -> (f32, f32) {
+        match direction {
+            Direction::Left | Direction::Right => (self.box_x, self.box_x + self.box_size()),
+            Direction::Up | Direction::Down => (self.box_y, self.box_y + self.box_size()),
+        }
+    }
+
+    /// Lane a route uses on its approach and, for turns, on its exit.
+    pub fn lane_index(&self, route: Route) -> u32 {
+        let n = self.lanes_per_approach;
+        match route.turning() {
+            Turning::Right => 0,
+            Turning::Left => n - 1,
+            Turning::None => (n / 2).min(n - 1),
+        }
+    }
+
+    /// Cross-axis coordinate of a car's top-left corner in `lane` for traffic moving in `direction`.
+    pub fn lane_coordinate(&self, direction: Direction, lane: u32) -> f32 {
+        let n = self.lanes_per_approach;
+        let padding = (self.lane_width - CAR_HEIGHT) / 2.0;
+        match direction {
+            Direction::Down => self.box_x + lane as f32 * self.lane_width + padding,
+            Direction::Up => self.box_x + (2 * n - 1 - lane) as f32 * self.lane_width + padding,
+            Direction::Left => self.box_y + lane as f32 * self.lane_width + padding,
+            Direction::Right => self.box_y + (2 * n - 1 - lane) as f32 * self.lane_width + padding,
+        }
+    }
+
+    /// Centre line a car on `route` follows, from just off the canvas through the
+    /// box until it has fully left the canvas again.
+    pub fn path(&self, route: Route) -> Path {
+        let approach = route.get_direction();
+        let exit = route.exit_direction();
+        let lane = self.lane_index(route);
+        let across = self.lane_coordinate(approach, lane) + CAR_HEIGHT / 2.0;
+        let half = CAR_LENGTH / 2.0;
+        let start = match approach {
+            Direction::Down => vec2(across, -half),
+            Direction::Up => vec2(across, self.canvas_height + half),
+            Direction::Right => vec2(-half, across),
+            Direction::Left => vec2(self.canvas_width + half, across),
+        };
+        let (box_start, box_end) = self.box_range(approach);
+        let box_entry = match approach {
+            Direction::Down => box_start - start.y,
+            Direction::Up => start.y - box_end,
+            Direction::Right => box_start - start.x,
+            Direction::Left => start.x - box_end,
+        };
+        let entry_point = start + approach.vector() * box_entry;
+        // a turn is a quarter circle from the box edge to the exit lane, tangent to both lanes
+        let radius = if route.turning() == Turning::None {
+            0.0
+        } else {
+            let exit_across = self.lane_coordinate(exit, lane) + CAR_HEIGHT / 2.0;
+            let corner = match approach {
+                Direction::Up | Direction::Down => vec2(across, exit_across),
+                Direction::Left | Direction::Right => vec2(exit_across, across),
+            };
+            entry_point.distance(corner)
+        };
+        let exit_point = if radius > 0.0 {
+            entry_point + (approach.vector() + exit.vector()) * radius
+        } else {
+            entry_point + approach.vector() * self.box_size()
+        };
+        let exit_length = match exit {
+            Direction::Down => self.canvas_height - exit_point.y,
+            Direction::Up => exit_point.y,
+            Direction::Right => self.canvas_width - exit_point.x,
+            Direction::Left => exit_point.x,
+        } + half;
+        Path::new(start, approach.vector(), exit.vector(), box_entry, radius, self.box_size(), exit_length)
+    }
+}
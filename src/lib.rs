@@ -1,23 +1,70 @@
-use std::borrow::{Borrow, BorrowMut};
-use std::collections::{HashMap, HashSet, VecDeque};
-use macroquad::color::Color;
-use macroquad::prelude::*;
-use crate::miniquad::native::apple::frameworks::sel;
+#![allow(non_snake_case)]
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use macroquad::math::{vec2, Vec2};
+use r::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+pub mod collision;
+pub mod controller;
+pub mod grid;
+pub mod layout;
+pub mod network;
+pub mod path;
+pub mod replay;
+pub mod report;
+pub mod reservation;
+pub mod routing;
+pub mod scenario;
+pub mod snapshot;
+pub mod traffic;
+pub mod traffic_light;
+pub mod vehicle;
+#[cfg(test)]
+mod testing;
+
+use crate::collision::{CloseCall, CollisionEvent, Obb};
+use crate::controller::{Context, Controller, QueueController};
+use crate::grid::SpatialGrid;
+use crate::layout::IntersectionLayout;
+use crate::path::Path;
+use crate::replay::{Input, Recording};
+use crate::snapshot::Snapshot;
+use crate::vehicle::{VehicleKind, VehicleMix};
+
+// Size of a plain car; other vehicle kinds have their own, see `VehicleKind::size`.
 pub const CAR_HEIGHT: f32 = 10_f32;
 pub const CAR_LENGTH: f32 = 30_f32;
 
-const CAR_SPEED_NORMAL: f32 = 1.5;
-const CAR_SPEED_SLOW: f32 = 0.3;
-const CAR_SPEED_FAST: f32 = 3.5;
+// Speeds are in pixels per second.
+pub const CAR_SPEED_NORMAL: f32 = 90.0;
+pub const CAR_SPEED_SLOW: f32 = 18.0;
+pub const CAR_SPEED_FAST: f32 = 210.0;
+// Accelerations are in pixels per second squared.
+pub const CAR_ACCELERATION: f32 = 150.0;
+pub const CAR_DECELERATION: f32 = 300.0;
 
-const BEFORE_CROSS_ROAD: Vec2 = vec2(200.0, 580.0);
-const AFTER_CROSS_ROAD: Vec2 = vec2(300.0, 480.0);
-// const AFTER_FIRST_INTERSECTION: Vec2 = vec2(350.0, 430.0);
+/// Default gap in pixels cars have to keep to each other.
+pub const SAFETY_DISTANCE: f32 = 5.0;
+/// Default number of ticks without any car moving after which the intersection counts
+/// as deadlocked. Longer than a car can wait for a full fixed traffic-light cycle.
+pub const DEADLOCK_TICKS: u32 = 1800;
 
-pub const COLORS: &'static [Color] = &[LIME, RED, SKYBLUE, VIOLET, GREEN, GRAY, MAROON, MAGENTA];
+/// How close in pixels per second a car's speed has to stay to its last speed and to its
+/// target to count as cruising.
+const CRUISE_TOLERANCE: f32 = 0.5;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// Resolution in pixels of the look-ahead for obstacles in a car's way.
+const PROBE_STEP: f32 = 2.0;
+
+/// Length of one simulation tick in seconds.
+pub const TIME_STEP: f32 = 1.0 / 60.0;
+
+/// Number of colours a car can be painted with; the palette itself lives in the renderer.
+pub const COLORS_COUNT: usize = 8;
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum Direction {
     Left,
     Right,
@@ -25,206 +72,289 @@ pub enum Direction {
     Up,
 }
 
-#[derive(PartialEq)]
+impl Direction {
+    /// Unit vector pointing in this direction, in screen coordinates.
+    pub fn vector(&self) -> Vec2 {
+        match *self {
+            Direction::Left => vec2(-1.0, 0.0),
+            Direction::Right => vec2(1.0, 0.0),
+            Direction::Down => vec2(0.0, 1.0),
+            Direction::Up => vec2(0.0, -1.0),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum Turning {
     Left,
     Right,
     None,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// How much room a car has to leave to the car ahead of it.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum SafeDistance {
+    /// The same gap in pixels at any speed.
+    Fixed(f32),
+    /// `min_gap` pixels plus the distance covered in `headway` seconds at the current speed.
+    Headway { min_gap: f32, headway: f32 },
+}
+
+impl SafeDistance {
+    /// Gap required in front of a car driving at `speed`.
+    pub fn gap(&self, speed: f32) -> f32 {
+        match *self {
+            SafeDistance::Fixed(gap) => gap,
+            SafeDistance::Headway { min_gap, headway } => min_gap + headway * speed,
+        }
+    }
+
+    /// Gap required at standstill, which also applies between cars on different tracks.
+    pub fn min_gap(&self) -> f32 {
+        self.gap(0.0)
+    }
+
+    /// Highest speed at which a car `room` pixels behind its leader still keeps the required
+    /// gap after driving on for `dt`, and can brake to a stop with `deceleration` from there.
+    pub fn max_speed(&self, room: f32, deceleration: f32, dt: f32) -> f32 {
+        let headway = match *self {
+            SafeDistance::Fixed(_) => 0.0,
+            SafeDistance::Headway { headway, .. } => headway,
+        } + dt;
+        let spare = (room - self.min_gap()).max(0.0);
+        deceleration * ((headway * headway + 2.0 * spare / deceleration).sqrt() - headway)
+    }
+}
+
+impl Default for SafeDistance {
+    fn default() -> Self {
+        SafeDistance::Fixed(SAFETY_DISTANCE)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub struct Car {
     pub id: u32,
-    pub color: Color,
+    pub color: usize,
+    /// Sets the size, top speed and acceleration.
+    #[serde(default)]
+    pub kind: VehicleKind,
+    /// Centre of the car.
+    #[serde(with = "snapshot::vec2")]
     pub position: Vec2,
-    pub speed: (f32, f32),
+    /// Orientation in radians, clockwise from the x axis.
+    pub heading: f32,
+    pub path: Path,
+    /// How far the car has come along `path`.
+    pub distance: f32,
+    /// Current speed along the path, pixels per second.
+    pub speed: f32,
+    /// Speed the car ramps towards, set by the controller every tick.
+    pub target_speed: f32,
+    pub max_acceleration: f32,
+    pub max_deceleration: f32,
+    /// Length and width of the car.
     pub rectangle: (f32, f32),
-    pub direction: Direction,
     pub route: Route,
-    pub turned: bool,
+    // Simulation times in seconds.
+    pub spawned_at: f32,
+    pub entered_at: Option<f32>,
+    #[serde(default)]
+    pub exited_at: Option<f32>,
+    pub left_at: Option<f32>,
+}
+
+/// The intersection got stuck: nothing moved for `Intersection::deadlock_ticks`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Deadlock {
+    pub time: f32,
+    /// Cars waiting in front of or on the crossing.
+    pub cars: Vec<(u32, Route)>,
+    /// The oldest waiting car, which was let through regardless of the controller.
+    pub forced: Option<u32>,
+}
+
+/// Timestamps of a car that has left the intersection.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct CarTiming {
+    pub id: u32,
+    pub route: Route,
+    #[serde(default)]
+    pub kind: VehicleKind,
+    pub spawned_at: f32,
+    /// When the car reached the approach zone in front of the box.
+    pub entered_at: Option<f32>,
+    /// When the car's rear cleared the box.
+    #[serde(default)]
+    pub exited_at: Option<f32>,
+    pub left_at: f32,
+}
+
+impl CarTiming {
+    /// Time from spawning until the car drove away.
+    pub fn travel_time(&self) -> f32 {
+        self.left_at - self.spawned_at
+    }
+
+    /// Time from reaching the approach zone until clearing the box, waiting included.
+    pub fn crossing_time(&self) -> Option<f32> {
+        Some(self.exited_at? - self.entered_at?)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TimeStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+}
+
+impl TimeStats {
+    fn of(times: &[f32]) -> Option<TimeStats> {
+        if times.is_empty() {
+            return None;
+        }
+        Some(TimeStats {
+            min: times.iter().cloned().fold(f32::INFINITY, f32::min),
+            max: times.iter().cloned().fold(0.0, f32::max),
+            mean: times.iter().sum::<f32>() / times.len() as f32,
+        })
+    }
+}
+
+/// Nearest-rank percentile of `times`, `p` in 0..=100.
+fn percentile(mut times: Vec<f32>, p: f32) -> Option<f32> {
+    if times.is_empty() {
+        return None;
+    }
+    times.sort_by(f32::total_cmp);
+    let rank = (p.clamp(0.0, 100.0) / 100.0 * times.len() as f32).ceil() as usize;
+    Some(times[rank.saturating_sub(1)])
 }
 
 
 impl Car {
     pub fn new(
-        position: Vec2,
-        rectangle: (f32, f32),
-        color: Color,
-        speed: (f32, f32),
+        path: Path,
+        kind: VehicleKind,
+        color: usize,
+        speed: f32,
         id: u32,
-        direction: Direction,
         route: Route,
-        turned: bool,
+        spawned_at: f32,
     ) -> Car {
-        Car {
+        let rectangle = kind.size();
+        let (max_acceleration, max_deceleration) = kind.acceleration();
+        // every kind starts with its front where a car's would be, just at the canvas edge
+        let distance = (CAR_LENGTH - rectangle.0) / 2.0;
+        let speed = speed.min(kind.max_speed());
+        let mut car = Car {
             color,
+            kind,
             rectangle,
-            position,
+            position: path.point(distance),
+            heading: path.heading(distance),
+            path,
+            distance,
             speed,
+            target_speed: speed,
+            max_acceleration,
+            max_deceleration,
             id,
-            direction,
             route,
-            turned,
-        }
+            spawned_at,
+            entered_at: None,
+            exited_at: None,
+            left_at: None,
+        };
+        car.place();
+        car
     }
 
-    pub fn drive(&mut self) {
-        if self.on_turn_point() && !self.turned {
-            self.turn();
-        }
-        self.position = (
-            vec2(self.position.x + self.speed.0,
-                 self.position.y + self.speed.1, )
-        );
+    /// Ramps the speed towards `target_speed` within the car's limits, then moves along the path.
+    pub fn drive(&mut self, dt: f32) {
+        let change = (self.target_speed - self.speed).clamp(-self.max_deceleration * dt, self.max_acceleration * dt);
+        self.speed = (self.speed + change).max(0.0);
+        self.distance += self.speed * dt;
+        self.place();
     }
 
-    pub fn draw(&self) {
-        draw_rectangle(self.position.x, self.position.y, self.rectangle.0, self.rectangle.1, self.color);
+    /// Puts the body between its front and rear points on the path, so a long vehicle
+    /// cuts the inside of a turn instead of swinging out of its lane.
+    fn place(&mut self) {
+        let front = self.path.point(self.front());
+        let rear = self.path.point(self.rear());
+        self.position = (front + rear) / 2.0;
+        self.heading = (front - rear).y.atan2((front - rear).x);
     }
 
-    fn before_cross_road(&self) -> bool {
-        match self.direction {
-            Direction::Right => self.position.x < BEFORE_CROSS_ROAD.x,
-            Direction::Left => self.position.x > BEFORE_CROSS_ROAD.y,
-            Direction::Down => self.position.y < BEFORE_CROSS_ROAD.x,
-            Direction::Up => self.position.y > BEFORE_CROSS_ROAD.y,
-        }
+    fn front(&self) -> f32 {
+        self.distance + self.rectangle.0 / 2.0
     }
 
-    fn in_stop_zone(&self) -> bool {
-        return match self.direction {
-                    Direction::Right => self.position.x > AFTER_CROSS_ROAD.x - CAR_LENGTH,
-                    Direction::Left => self.position.x < AFTER_CROSS_ROAD.y,
-                    Direction::Down => self.position.y > AFTER_CROSS_ROAD.x - CAR_LENGTH,
-                    Direction::Up => self.position.y < AFTER_CROSS_ROAD.y,
-                }
+    fn rear(&self) -> f32 {
+        self.distance - self.rectangle.0 / 2.0
     }
 
-    fn after_cross_road(&self) -> bool {
-        match self.direction {
-            Direction::Right => self.position.x > AFTER_CROSS_ROAD.y,
-            Direction::Left => self.position.x < AFTER_CROSS_ROAD.x,
-            Direction::Down => self.position.y > AFTER_CROSS_ROAD.y,
-            Direction::Up => self.position.y < AFTER_CROSS_ROAD.x,
-        }
+    pub(crate) fn before_cross_road(&self, layout: &IntersectionLayout) -> bool {
+        self.front() < self.path.box_entry() - layout.approach_zone
     }
 
-    fn on_cross_road(&self) -> bool {
-        return !self.before_cross_road() && !self.after_cross_road();
+    fn in_stop_zone(&self) -> bool {
+        self.front() > self.path.box_entry()
     }
 
-    fn speed_up(&mut self) {
-        self.speed = match self.direction {
-            Direction::Down => (0.0, CAR_SPEED_FAST),
-            Direction::Up => (0.0, -CAR_SPEED_FAST),
-            Direction::Right => (CAR_SPEED_FAST, 0.0),
-            Direction::Left => (-CAR_SPEED_FAST, 0.0),
-        }
+    pub(crate) fn after_cross_road(&self) -> bool {
+        self.rear() > self.path.box_exit()
     }
 
-    fn is_speed_up(&self) -> bool {
-        return self.speed.0.abs() == CAR_SPEED_FAST || self.speed.1.abs() == CAR_SPEED_FAST;
+    pub(crate) fn on_cross_road(&self, layout: &IntersectionLayout) -> bool {
+        !self.before_cross_road(layout) && !self.after_cross_road()
     }
 
-    fn is_slow_down(&self) -> bool {
-        return self.speed.0.abs() == CAR_SPEED_SLOW || self.speed.1.abs() == CAR_SPEED_SLOW;
+    /// Whether the car has been told to drive below normal speed.
+    pub(crate) fn is_slow_down(&self) -> bool {
+        self.target_speed < CAR_SPEED_NORMAL
     }
 
-    fn slow_down(&mut self) {
-        self.speed = match self.direction {
-            Direction::Down => (0.0, CAR_SPEED_SLOW),
-            Direction::Up => (0.0, -CAR_SPEED_SLOW),
-            Direction::Right => (CAR_SPEED_SLOW, 0.0),
-            Direction::Left => (-CAR_SPEED_SLOW, 0.0),
-        }
+    /// Corners of the rotated car rectangle, going round.
+    pub fn corners(&self) -> [Vec2; 4] {
+        let along = vec2(self.heading.cos(), self.heading.sin()) * (self.rectangle.0 / 2.0);
+        let across = vec2(-self.heading.sin(), self.heading.cos()) * (self.rectangle.1 / 2.0);
+        let c = self.position;
+        [c + along + across, c + along - across, c - along - across, c - along + across]
     }
 
-    fn on_turn_point(&self) -> bool {
-        return match self.route {
-            Route::N_W => self.position.y > 300.0 && self.position.y < 350.0,
-            Route::S_E => self.position.y < 460.0 && self.position.y > 410.0,
-            Route::W_S => self.position.x > 300.0 && self.position.x < 350.0,
-            Route::E_N => self.position.x < 460.0 && self.position.x > 350.0,
+    /// Axis-aligned box around the rotated car, as top-left and bottom-right corner.
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        let corners = self.corners();
+        let min = corners.iter().fold(corners[0], |m, c| m.min(*c));
+        let max = corners.iter().fold(corners[0], |m, c| m.max(*c));
+        (min, max)
+    }
 
-            Route::N_E => self.position.y > 390.0 && self.position.y < 440.0,
-            Route::S_W => self.position.y > 320.0 && self.position.y < 370.0,
-            Route::W_N => self.position.x > 390.0 && self.position.x < 440.0,
-            Route::E_S => self.position.x > 320.0 && self.position.x < 370.0,
-            _ => false,
-        };
+    /// Current speed in pixels per second.
+    pub fn velocity(&self) -> f32 {
+        self.speed
     }
 
-    fn turn(&mut self) {
-        let speed = self.speed;
-        let r = self.rectangle;
-        self.rectangle.0 = r.1;
-        self.rectangle.1 = r.0;
-        self.turned = true;
+    /// Room between this car and `leader` along their common path.
+    pub fn distance_to(&self, leader: &Car) -> f32 {
+        leader.rear() - self.front()
+    }
 
-        match self.route {
-            Route::N_E => {
-                self.speed.0 = speed.1;
-                self.speed.1 = speed.0;
-                self.direction = Direction::Right;
-                self.position.y = 400.0;
-            }
-            Route::S_W => {
-                self.speed.0 = speed.1;
-                self.speed.1 = speed.0;
-                self.direction = Direction::Left;
-                self.position.y = 370.0;
-            }
-            Route::W_N => {
-                self.speed.0 = -speed.1;
-                self.speed.1 = -speed.0;
-                self.direction = Direction::Up;
-                self.position.x = 400.0;
-            }
-            Route::E_S => {
-                self.speed.0 = -speed.1;
-                self.speed.1 = -speed.0;
-                self.direction = Direction::Down;
-                self.position.x = 370.0;
-            }
-            Route::N_W => {
-                self.speed.0 = -speed.1;
-                self.speed.1 = -speed.0;
-                self.direction = Direction::Left;
-                self.position.y = 310.0;
-            }
-            Route::S_E => {
-                self.speed.0 = -speed.1;
-                self.speed.1 = -speed.0;
-                self.position.y = 460.0;
-                self.direction = Direction::Right;
-            }
-            Route::W_S => {
-                self.direction = Direction::Down;
-                self.speed.0 = speed.1;
-                self.speed.1 = speed.0;
-                self.position.x = 310.0;
-            }
-            Route::E_N => {
-                self.direction = Direction::Up;
-                self.speed.0 = speed.1;
-                self.speed.1 = speed.0;
-                self.position.x = 460.0;
-            }
-            _ => return,
-        }
+    /// Distance between the two rotated car rectangles, negative when they overlap.
+    pub fn gap(&self, other: &Car) -> f32 {
+        Obb::of(self).separation(&Obb::of(other))
     }
 
     fn drive_away(&self) -> bool {
-        return match self.direction {
-            Direction::Right => self.position.x > 800.0,
-            Direction::Left => self.position.x < 0.0 - CAR_LENGTH,
-            Direction::Down => self.position.y > 800.0,
-            Direction::Up => self.position.y < 0.0 - CAR_LENGTH,
-        }
+        self.rear() >= self.path.length() - CAR_LENGTH / 2.0
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum Route {
     N_S,
     S_N,
@@ -243,44 +373,31 @@ pub enum Route {
 }
 
 impl Route {
-    fn get_coordinates(&self) -> Vec2 {
+    pub const ALL: [Route; 12] = [
+        Route::N_S, Route::S_N, Route::W_E, Route::E_W,
+        Route::N_W, Route::S_E, Route::W_S, Route::E_N,
+        Route::N_E, Route::S_W, Route::W_N, Route::E_S,
+    ];
+
+    pub fn turning(&self) -> Turning {
         match *self {
-            Route::N_S => vec2(340_f32, 0_f32 - CAR_LENGTH),
-            Route::S_N => vec2(430_f32, 800_f32),
-            Route::W_E => vec2(0_f32 - CAR_LENGTH, 430_f32),
-            Route::E_W => vec2(800_f32, 340_f32),
-
-            Route::N_W => vec2(310_f32, 0_f32 - CAR_LENGTH),
-            Route::S_E => vec2(460_f32, 800_f32),
-            Route::W_S => vec2(0_f32 - CAR_LENGTH, 460_f32),
-            Route::E_N => vec2(800_f32, 310_f32),
-
-            Route::N_E => vec2(370_f32, 0f32 - CAR_LENGTH),
-            Route::S_W => vec2(400_f32, 800_f32),
-            Route::W_N => vec2(0f32 - CAR_LENGTH, 400f32),
-            Route::E_S => vec2(800f32, 370f32),
+            Route::N_S | Route::S_N | Route::W_E | Route::E_W => Turning::None,
+            Route::N_W | Route::S_E | Route::W_S | Route::E_N => Turning::Right,
+            Route::N_E | Route::S_W | Route::W_N | Route::E_S => Turning::Left,
         }
     }
-    fn get_speed(&self) -> (f32, f32) {
-        match *self {
-            Route::N_S => (0.0, CAR_SPEED_NORMAL),
-            Route::N_W => (0.0, CAR_SPEED_NORMAL),
-            Route::N_E => (0.0, CAR_SPEED_NORMAL),
-
-            Route::S_N => (0.0, -CAR_SPEED_NORMAL),
-            Route::S_E => (0.0, -CAR_SPEED_NORMAL),
-            Route::S_W => (0.0, -CAR_SPEED_NORMAL),
 
-            Route::W_E => (CAR_SPEED_NORMAL, 0.0),
-            Route::W_S => (CAR_SPEED_NORMAL, 0.0),
-            Route::W_N => (CAR_SPEED_NORMAL, 0.0),
-
-            Route::E_W => (-CAR_SPEED_NORMAL, 0.0),
-            Route::E_N => (-CAR_SPEED_NORMAL, 0.0),
-            Route::E_S => (-CAR_SPEED_NORMAL, 0.0),
+    /// Direction of travel after the car has left the crossing.
+    pub fn exit_direction(&self) -> Direction {
+        match *self {
+            Route::N_S | Route::E_S | Route::W_S => Direction::Down,
+            Route::S_N | Route::E_N | Route::W_N => Direction::Up,
+            Route::W_E | Route::N_E | Route::S_E => Direction::Right,
+            Route::E_W | Route::N_W | Route::S_W => Direction::Left,
         }
     }
-    fn get_direction(&self) -> Direction {
+
+    pub fn get_direction(&self) -> Direction {
         match *self {
             Route::N_S => Direction::Down,
             Route::N_W => Direction::Down,
@@ -300,6 +417,12 @@ impl Route {
         }
     }
 
+    /// Whether cars on the two routes can get in each other's way: they share a lane
+    /// or their paths cross. Every route has lanes of its own, so all others run apart.
+    pub(crate) fn conflicts_with(&self, other: Route) -> bool {
+        *self == other || self.not_allowed_to_go().contains(&other) || other.not_allowed_to_go().contains(self)
+    }
+
     fn not_allowed_to_go(&self) -> Vec<Route> {
         match *self {
             Route::N_S => vec![Route::E_W, Route::W_E, Route::W_N, Route::S_W],
@@ -320,202 +443,528 @@ impl Route {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Intersection {
-    tracks: HashMap<Route, Vec<u32>>,
+    layout: IntersectionLayout,
+    // saved separately by `Snapshot`
+    #[serde(skip, default = "default_controller")]
+    controller: Box<dyn Controller>,
+    seed: u64,
+    rng: ChaCha12Rng,
+    tracks: BTreeMap<Route, Vec<u32>>,
     car_id: u32,
-    occupied_tracks: HashMap<Route, HashSet<u32>>,
     cars: HashMap<u32, Car>,
-    queue: VecDeque<u32>,
+    #[serde(skip)]
+    grid: SpatialGrid,
 
     pub number_of_passed_vehicles: u32,
     pub max_velocity: f32,
-    pub min_velocity: f32,
+    /// Lowest speed any car cruised at, i.e. held at what its controller asked for; ramping up
+    /// or braking does not count. `None` until a car has cruised.
+    pub min_velocity: Option<f32>,
     pub collapse: u32,
     pub close_calls: u32,
+    pub safe_distance: SafeDistance,
+    pub deadlock_ticks: u32,
+    #[serde(default)]
+    pub vehicle_mix: VehicleMix,
+    time: f32,
+    ticks: u64,
+    // Every car requested so far, for `recording`.
+    inputs: Vec<Input>,
+    finished: Vec<CarTiming>,
+    collisions: Vec<CollisionEvent>,
+    close_call_events: Vec<CloseCall>,
+    stalled_ticks: u32,
+    // Cars let through to resolve a deadlock.
+    forced: HashSet<u32>,
+    deadlocks: Vec<Deadlock>,
+    // Pairs currently overlapping / too close, so an incident is counted once, not every tick.
+    colliding: HashSet<(u32, u32)>,
+    too_close: HashSet<(u32, u32)>,
+}
+
+impl Default for Intersection {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Intersection {
+    /// Creates an intersection with a random seed; use `seed()` to log it for a later rerun.
     pub fn new() -> Intersection {
+        Intersection::with_seed(r::random())
+    }
+
+    /// Creates an intersection whose car spawning is fully determined by `seed`.
+    pub fn with_seed(seed: u64) -> Intersection {
+        Intersection::with_layout(seed, IntersectionLayout::default())
+    }
+
+    pub fn with_layout(seed: u64, layout: IntersectionLayout) -> Intersection {
         Intersection {
-            tracks: HashMap::new(),
+            layout,
+            controller: Box::new(QueueController::new()),
+            seed,
+            rng: ChaCha12Rng::seed_from_u64(seed),
+            tracks: BTreeMap::new(),
             car_id: 0,
-            occupied_tracks: HashMap::new(),
             cars: HashMap::new(),
-            queue: VecDeque::new(),
+            grid: SpatialGrid::new(),
             number_of_passed_vehicles: 0,
             max_velocity: 0.0,
-            min_velocity: 0.0,
+            min_velocity: None,
             collapse: 0,
             close_calls: 0,
+            safe_distance: SafeDistance::default(),
+            deadlock_ticks: DEADLOCK_TICKS,
+            vehicle_mix: VehicleMix::default(),
+            stalled_ticks: 0,
+            forced: HashSet::new(),
+            deadlocks: Vec::new(),
+            time: 0.0,
+            ticks: 0,
+            inputs: Vec::new(),
+            finished: Vec::new(),
+            collisions: Vec::new(),
+            close_call_events: Vec::new(),
+            colliding: HashSet::new(),
+            too_close: HashSet::new(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn layout(&self) -> &IntersectionLayout {
+        &self.layout
+    }
+
+    pub fn controller(&self) -> &dyn Controller {
+        self.controller.as_ref()
+    }
+
+    /// Replaces the right-of-way strategy; the default is `QueueController`.
+    pub fn set_controller(&mut self, controller: Box<dyn Controller>) {
+        self.controller = controller;
+    }
+
+    /// Spawns a car on one of `routes`, picked at random; returns its id, or `None`
+    /// if the lane is still too full at the canvas edge.
+    pub fn add_car(&mut self, routes: Vec<Route>) -> Option<u32> {
+        self.inputs.push(Input::AddCar { tick: self.ticks, routes: routes.clone() });
+        let route: Route = generate_route(&mut self.rng, routes);
+        self.spawn_car(route, None)
+    }
+
+    /// Spawns a car on exactly `route`; returns its id, or `None` if the lane is still
+    /// too full at the canvas edge.
+    pub fn spawn(&mut self, route: Route) -> Option<u32> {
+        // a refused spawn changes nothing, so only successful ones need replaying
+        let id = self.spawn_car(route, None);
+        if id.is_some() {
+            self.inputs.push(Input::Spawn { tick: self.ticks, route, vehicle: None });
         }
+        id
     }
 
-    pub fn add_car(&mut self, routes: Vec<Route>) {
-        let route: Route = generate_route(routes);
+    /// Like `spawn`, but the vehicle is of `kind` instead of one picked from `vehicle_mix`,
+    /// e.g. a vehicle coming in from another intersection.
+    pub fn spawn_kind(&mut self, route: Route, kind: VehicleKind) -> Option<u32> {
+        let id = self.spawn_car(route, Some(kind));
+        if id.is_some() {
+            self.inputs.push(Input::Spawn { tick: self.ticks, route, vehicle: Some(kind) });
+        }
+        id
+    }
+
+    fn spawn_car(&mut self, route: Route, kind: Option<VehicleKind>) -> Option<u32> {
         if !self.can_add(route) {
-            return;
+            return None;
         }
 
         self.car_id += 1;
 
-        let mut rectangle: (f32, f32) = (CAR_LENGTH, CAR_HEIGHT);
-        let direction = route.get_direction();
-        if direction == Direction::Up || direction == Direction::Down {
-            rectangle = (CAR_HEIGHT, CAR_LENGTH);
-        }
-        let n: usize = rand::gen_range(0, COLORS.len());
+        let color: usize = self.rng.gen_range(0..COLORS_COUNT);
+        let kind = kind.unwrap_or_else(|| self.vehicle_mix.pick(&mut self.rng));
 
         let car = Car::new(
-            route.get_coordinates(),
-            rectangle,
-            COLORS[n],
-            route.get_speed(),
+            self.layout.path(route),
+            kind,
+            color,
+            CAR_SPEED_NORMAL,
             self.car_id,
-            direction,
             route,
-            false,
+            self.time,
         );
 
-        let current_cars_on_track = self.tracks.get_mut(&route);
-        let mut cars = match current_cars_on_track {
-            Some(value) => value.to_vec(),
-            None => Vec::new(),
-        };
-        cars.push(car.id);
-        self.tracks.get_mut(&route);
-        self.tracks.insert(route, cars.clone());
+        self.tracks.entry(route).or_default().push(car.id);
+        let (min, max) = car.bounds();
+        self.grid.update(car.id, min, max);
         self.cars.insert(car.id, car);
+        Some(car.id)
     }
 
-    fn can_add(&mut self, route: Route) -> bool {
-        let start_coordinates = route.get_coordinates();
-        let mut cars = self.tracks.get_mut(&route);
-        return match cars {
-            Some(cars) => {
-                let last_car_id = cars.as_slice().last().unwrap();
-                let last_car_position = self.cars.get(last_car_id).unwrap().position;
-                if (route == Route::N_S || route == Route::N_W || route == Route::N_E) && last_car_position.y <= start_coordinates.y + CAR_LENGTH * 2.0 {
-                    return false;
-                }
-                if (route == Route::S_N || route == Route::S_E || route == Route::S_W) && last_car_position.y + CAR_LENGTH * 2.0 >= start_coordinates.y {
-                    return false;
-                }
-                if (route == Route::W_E || route == Route::W_S || route == Route::W_N) && last_car_position.x <= start_coordinates.x + CAR_LENGTH * 2.0 {
-                    return false;
-                }
-                if (route == Route::E_W || route == Route::E_N || route == Route::E_S) && last_car_position.x + CAR_LENGTH * 2.0 >= start_coordinates.x {
-                    return false;
-                }
-                true
+    fn can_add(&self, route: Route) -> bool {
+        match self.tracks.get(&route).and_then(|cars| cars.last()) {
+            Some(last_car_id) => {
+                // a new vehicle's front is where a car's would be, half a car length in
+                let room = self.cars[last_car_id].rear() - CAR_LENGTH / 2.0;
+                room > CAR_LENGTH.max(self.safe_distance.gap(CAR_SPEED_NORMAL))
             }
             None => true,
-        };
+        }
     }
 
-    pub fn draw_cars(&self) {
-        for (_route, cars) in self.tracks.iter() {
-            cars.iter().for_each(|id| {
-                let car = self.cars.get(id).unwrap();
-                car.draw();
-            })
+    pub fn car(&self, id: u32) -> Option<&Car> {
+        self.cars.get(&id)
+    }
+
+    pub fn cars(&self) -> impl Iterator<Item = &Car> {
+        self.tracks.values().flatten().filter_map(|id| self.cars.get(id))
+    }
+
+    /// The whole state of the simulation, to save and continue from later.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            controller: self.controller.name().to_string(),
+            controller_state: self.controller.state(),
+            intersection: self.clone(),
+        }
+    }
+
+    /// Fills the spatial index from scratch, after the cars were loaded.
+    pub(crate) fn rebuild_grid(&mut self) {
+        self.grid = SpatialGrid::new();
+        for car in self.cars.values() {
+            let (min, max) = car.bounds();
+            self.grid.update(car.id, min, max);
+        }
+    }
+
+    /// Ticks simulated since the intersection was created.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Setup and inputs of the run so far, to replay it later.
+    pub fn recording(&self) -> Recording {
+        Recording {
+            seed: self.seed,
+            layout: self.layout,
+            controller: self.controller.name().to_string(),
+            safe_distance: self.safe_distance,
+            deadlock_ticks: self.deadlock_ticks,
+            vehicle_mix: self.vehicle_mix,
+            ticks: self.ticks,
+            inputs: self.inputs.clone(),
         }
     }
 
-    pub fn drive_cars(&mut self) {
-        for (route, cars_ids) in self.tracks.iter() {
+    /// Simulated seconds since the intersection was created.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Timings of every car that has driven away, in the order they left.
+    pub fn finished_cars(&self) -> &[CarTiming] {
+        &self.finished
+    }
+
+    /// Every collision so far, in the order they happened.
+    pub fn collisions(&self) -> &[CollisionEvent] {
+        &self.collisions
+    }
+
+    /// Every safe-distance violation so far, in the order they happened.
+    pub fn close_call_events(&self) -> &[CloseCall] {
+        &self.close_call_events
+    }
+
+    /// Every deadlock so far and how it was resolved.
+    pub fn deadlocks(&self) -> &[Deadlock] {
+        &self.deadlocks
+    }
+
+    fn travel_times(&self) -> Vec<f32> {
+        self.finished.iter().map(|t| t.travel_time()).collect()
+    }
+
+    fn crossing_times(&self) -> Vec<f32> {
+        self.finished.iter().filter_map(|t| t.crossing_time()).collect()
+    }
+
+    /// Spawn to drive-away times of the cars that left, see `CarTiming::travel_time`.
+    pub fn travel_time_stats(&self) -> Option<TimeStats> {
+        TimeStats::of(&self.travel_times())
+    }
+
+    /// Nearest-rank percentile of travel times, `p` in 0..=100.
+    pub fn travel_time_percentile(&self, p: f32) -> Option<f32> {
+        percentile(self.travel_times(), p)
+    }
+
+    /// Time the cars that left spent at the intersection, see `CarTiming::crossing_time`.
+    pub fn crossing_time_stats(&self) -> Option<TimeStats> {
+        TimeStats::of(&self.crossing_times())
+    }
+
+    /// Nearest-rank percentile of crossing times, `p` in 0..=100.
+    pub fn crossing_time_percentile(&self, p: f32) -> Option<f32> {
+        percentile(self.crossing_times(), p)
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        self.time += dt;
+        self.ticks += 1;
+        let context = Context { layout: &self.layout, time: self.time, cars: &self.cars, leader: None };
+        self.controller.tick(&context);
+        let moved = self.drive_cars(dt);
+        self.detect_deadlock(moved);
+        self.detect_incidents();
+        self.remove_cars();
+    }
+
+    /// Moves every car one tick; returns whether any of them got anywhere.
+    ///
+    /// Tracks are handled in `Route::ALL` order, starting one route further every tick,
+    /// so runs are reproducible and no approach is always asked first.
+    pub fn drive_cars(&mut self, dt: f32) -> bool {
+        let mut moved = false;
+        let first = (self.ticks % Route::ALL.len() as u64) as usize;
+        for route in Route::ALL.iter().cycle().skip(first).take(Route::ALL.len()) {
+            let Some(cars_ids) = self.tracks.get(route) else {
+                continue;
+            };
             for (ind, car_id) in cars_ids.iter().enumerate() {
-                let mut cars = self.cars.clone();
-                let cars_on_cross_road = self.occupied_tracks.get(route);
-                let mut car: &mut Car = self.cars.get_mut(car_id).unwrap();
-                let mut can_go = route.not_allowed_to_go().len() == 0 || self.queue.is_empty() || self.queue[0] == car.id;
-
-                route.not_allowed_to_go().iter().for_each(|r| {
-                    let not_speed_up = match self.occupied_tracks.get(r) {
-                        Some(a) => {
-                            let mut res = true;
-                            a.iter().for_each(|f| {
-                                res = res && !cars.get(f).unwrap().is_speed_up();
-                            });
-                            res
-                        }
-                        None => true
-                    };
-                    can_go = can_go && (self.occupied_tracks.get(r).is_none() || not_speed_up);
-                });
-
-                if !cars_on_cross_road.is_none() {
-                    let mut all_cars = cars_on_cross_road.unwrap().clone();
-                    if !car.before_cross_road() && !car.is_speed_up() {
-                        if can_go {
-                            car.speed_up();
-                            if !self.queue.is_empty() && self.queue[0] == car.id {
-                                self.queue.pop_front();
-                            }
-                        } else {
-                            car.slow_down();
-                            if !self.queue.contains(&car.id) {
-                                self.queue.push_back(car.id);
-                            }
-                        }
-                        all_cars.insert(car.id);
-                    } else if car.after_cross_road() {
-                        all_cars.remove(&car.id);
-                    }
-                    if all_cars.is_empty() {
-                        self.occupied_tracks.remove(route);
-                    } else {
-                        self.occupied_tracks.insert(*route, all_cars);
+                let leader = ind.checked_sub(1).and_then(|i| self.cars.get(&cars_ids[i]));
+                let context = Context {
+                    layout: &self.layout,
+                    time: self.time,
+                    cars: &self.cars,
+                    leader,
+                };
+                let mut decision = self.controller.decide(&self.cars[car_id], &context);
+                if self.forced.contains(car_id) {
+                    decision.target_speed = decision.target_speed.max(CAR_SPEED_NORMAL);
+                    decision.cleared = true;
+                }
+                let cleared = decision.cleared;
+                let mut car = self.cars[car_id];
+                let decided = decision.target_speed.min(car.kind.max_speed());
+                car.target_speed = decided;
+                // whatever the controller says, stay able to stop at a safe distance behind the leader
+                if let Some(leader) = leader {
+                    let max_speed = self.safe_distance.max_speed(car.distance_to(leader), car.max_deceleration, dt);
+                    car.target_speed = car.target_speed.min(max_speed);
+                }
+                // brake in time for whatever is in the way, within the car's deceleration
+                if let Some(room) = self.room_ahead(&car, cleared, dt) {
+                    let d = car.max_deceleration;
+                    let max_speed = d * ((dt * dt + 2.0 * room / d).sqrt() - dt);
+                    car.target_speed = car.target_speed.min(max_speed);
+                }
+                let mut car_clone = car;
+                car_clone.drive(dt);
+                if !self.blocked(&car_clone, cleared) {
+                    let (distance, speed) = (car.distance, car.speed);
+                    car.drive(dt);
+                    moved = moved || car.distance > distance;
+                    let velocity = car.velocity();
+                    self.max_velocity = self.max_velocity.max(velocity);
+                    // holding the controller's speed, not ramping or braking to one capped for this tick
+                    let cruising = velocity > 0.0 && (velocity - speed).abs() < CRUISE_TOLERANCE
+                        && (velocity - decided).abs() < CRUISE_TOLERANCE;
+                    if cruising && self.min_velocity.is_none_or(|min| velocity < min) {
+                        self.min_velocity = Some(velocity);
                     }
-                } else if car.on_cross_road() {
-                    let cars = HashSet::from_iter(vec![car.id]);
-                    if can_go {
-                        car.speed_up();
-                        if !self.queue.is_empty() && self.queue[0] == car.id {
-                            self.queue.pop_front();
-                        }
-                    } else {
-                        car.slow_down();
-                        if !self.queue.contains(&car.id) {
-                            self.queue.push_back(car.id);
-                        }
+                    if car.entered_at.is_none() && car.on_cross_road(&self.layout) {
+                        car.entered_at = Some(self.time);
                     }
-                    self.occupied_tracks.insert(*route, cars);
-                }
-                if car.before_cross_road() && ind >= 1 {
-                    if !cars.get(&cars_ids[ind - 1]).is_none() && cars.get(&cars_ids[ind - 1]).unwrap().is_slow_down() {
-                        car.slow_down();
-                    } else {
-                        car.speed = route.get_speed();
+                    if car.exited_at.is_none() && car.after_cross_road() {
+                        car.exited_at = Some(self.time);
                     }
-                }
-                let mut car_clone = car.clone();
-                car_clone.drive();
-                if (car_clone.is_speed_up() || !car_clone.in_stop_zone())&& !cars.values().any(|c| {
-                    c.id != car_clone.id && intersect(car_clone.position, c.position,
-                                                      vec2(car_clone.position.x + car_clone.rectangle.0 + 5.0, car_clone.position.y + car_clone.rectangle.1 + 5.0),
-                                                      vec2(c.position.x + c.rectangle.0 + 5.0, c.position.y + c.rectangle.1 + 5.0))
-                }) {
-                    car.drive();
                     if car.drive_away() {
+                        car.left_at = Some(self.time);
+                        self.finished.push(CarTiming {
+                            id: car.id,
+                            route: car.route,
+                            kind: car.kind,
+                            spawned_at: car.spawned_at,
+                            entered_at: car.entered_at,
+                            exited_at: car.exited_at,
+                            left_at: self.time,
+                        });
+                        self.controller.car_left(*car_id);
+                        self.forced.remove(car_id);
+                        self.grid.remove(*car_id);
                         self.cars.remove(car_id);
                         self.number_of_passed_vehicles += 1;
+                        continue;
+                    }
+                    let (min, max) = car.bounds();
+                    self.grid.update(car.id, min, max);
+                } else {
+                    // something got in the way too suddenly to plan for: hold, braking as hard as the car can
+                    car.speed = (car.speed - car.max_deceleration * dt).max(0.0);
+                }
+                // the caps only hold for this tick, controllers keep seeing their own decision
+                car.target_speed = decided;
+                self.cars.insert(car.id, car);
+            }
+        }
+        moved
+    }
+
+    /// Whether a car moved to where `ghost` is would be in the way: in the box without
+    /// clearance, closer than the safe distance to a car on a conflicting route, or
+    /// touching any car at all.
+    fn blocked(&self, ghost: &Car, cleared: bool) -> bool {
+        let (min, max) = ghost.bounds();
+        let obstacles = self.obstacles(ghost, min, max);
+        (!cleared && ghost.in_stop_zone()) || obstacles.iter().any(|(obb, keep)| Obb::of(ghost).padded(*keep).overlaps(obb))
+    }
+
+    /// Other cars within `min`..`max` plus the safe distance, each with the gap `car` has to keep to it.
+    fn obstacles(&self, car: &Car, min: Vec2, max: Vec2) -> Vec<(Obb, f32)> {
+        let min_gap = self.safe_distance.min_gap();
+        let padding = vec2(min_gap, min_gap);
+        self.grid.near(min - padding, max + padding).iter()
+            .filter(|id| **id != car.id)
+            .map(|id| {
+                let other = &self.cars[id];
+                let keep = if car.route.conflicts_with(other.route) { min_gap } else { 0.0 };
+                (Obb::of(other), keep)
+            })
+            .collect()
+    }
+
+    /// How far `car` can drive along its path before it is blocked, if that is within
+    /// its braking distance plus one tick; other cars are taken to stand still.
+    fn room_ahead(&self, car: &Car, cleared: bool, dt: f32) -> Option<f32> {
+        let speed = car.speed.max(car.target_speed);
+        let horizon = speed * speed / (2.0 * car.max_deceleration) + speed * dt;
+        let steps = (horizon / PROBE_STEP).ceil() as usize;
+        let ghosts: Vec<Car> = (1..=steps).map(|i| {
+            let mut ghost = *car;
+            ghost.distance += i as f32 * PROBE_STEP;
+            ghost.place();
+            ghost
+        }).collect();
+        let (mut min, mut max) = car.bounds();
+        for ghost in &ghosts {
+            let (lo, hi) = ghost.bounds();
+            min = min.min(lo);
+            max = max.max(hi);
+        }
+        let obstacles = self.obstacles(car, min, max);
+        ghosts.iter()
+            .position(|ghost| {
+                (!cleared && ghost.in_stop_zone())
+                    || obstacles.iter().any(|(obb, keep)| Obb::of(ghost).padded(*keep).overlaps(obb))
+            })
+            .map(|i| i as f32 * PROBE_STEP)
+    }
+
+    /// Once no car has moved for `deadlock_ticks`, records a deadlock and lets the
+    /// oldest car waiting at the head of its track through, whatever the controller says.
+    fn detect_deadlock(&mut self, moved: bool) {
+        if moved || self.cars.is_empty() {
+            self.stalled_ticks = 0;
+            return;
+        }
+        self.stalled_ticks += 1;
+        if self.stalled_ticks < self.deadlock_ticks {
+            return;
+        }
+        self.stalled_ticks = 0;
+        let mut waiting: Vec<&Car> = self.cars.values()
+            .filter(|c| !c.before_cross_road(&self.layout) && !c.after_cross_road())
+            .collect();
+        waiting.sort_by_key(|c| c.id);
+        let forced = self.tracks.values()
+            .filter_map(|ids| ids.first().and_then(|id| self.cars.get(id)))
+            .filter(|c| !c.after_cross_road() && !self.forced.contains(&c.id))
+            .min_by(|a, b| a.spawned_at.total_cmp(&b.spawned_at).then(a.id.cmp(&b.id)))
+            .map(|c| c.id);
+        self.deadlocks.push(Deadlock {
+            time: self.time,
+            cars: waiting.iter().map(|c| (c.id, c.route)).collect(),
+            forced,
+        });
+        if let Some(id) = forced {
+            self.forced.insert(id);
+            self.controller.force_grant(id);
+        }
+    }
+
+    /// Records every new pair of overlapping cars as a collision, and every new pair
+    /// violating `safe_distance` as a close call: any two cars closer than its minimal
+    /// gap, and a car closer to the one ahead on its track than its speed allows.
+    fn detect_incidents(&mut self) {
+        let mut cars: Vec<&Car> = self.cars.values().collect();
+        cars.sort_by_key(|c| c.id);
+        let reach = vec2(self.safe_distance.min_gap(), self.safe_distance.min_gap());
+        let mut colliding = HashSet::new();
+        let mut too_close = HashMap::new();
+        for a in cars {
+            let (min, max) = a.bounds();
+            for id in self.grid.near(min - reach, max + reach) {
+                if id <= a.id {
+                    continue;
+                }
+                let b = &self.cars[&id];
+                let pair = (a.id, b.id);
+                let gap = a.gap(b);
+                if gap <= 0.0 {
+                    if !self.colliding.contains(&pair) {
+                        self.collisions.push(CollisionEvent {
+                            cars: pair,
+                            position: (a.position + b.position) / 2.0,
+                            time: self.time,
+                        });
+                        self.collapse += 1;
                     }
+                    colliding.insert(pair);
+                } else if gap < self.safe_distance.min_gap() && a.route.conflicts_with(b.route) {
+                    // side by side in parallel lanes is not a close call
+                    too_close.insert(pair, gap);
                 }
             }
         }
+        for ids in self.tracks.values() {
+            for ids in ids.windows(2) {
+                if let (Some(leader), Some(follower)) = (self.cars.get(&ids[0]), self.cars.get(&ids[1])) {
+                    let gap = follower.distance_to(leader);
+                    let pair = (leader.id.min(follower.id), leader.id.max(follower.id));
+                    if gap > 0.0 && gap < self.safe_distance.gap(follower.speed) && !colliding.contains(&pair) {
+                        too_close.insert(pair, gap);
+                    }
+                }
+            }
+        }
+        let mut new_close_calls: Vec<CloseCall> = too_close.iter()
+            .filter(|(pair, _)| !self.too_close.contains(*pair))
+            .map(|(pair, gap)| CloseCall { cars: *pair, gap: *gap, time: self.time })
+            .collect();
+        new_close_calls.sort_by_key(|c| c.cars);
+        self.close_calls += new_close_calls.len() as u32;
+        self.close_call_events.extend(new_close_calls);
+        self.colliding = colliding;
+        self.too_close = too_close.into_keys().collect();
     }
 
     pub fn remove_cars(&mut self) {
-        let mut map: HashMap<Route, Vec<u32>> = HashMap::new();
-        for(route, cars)in self.tracks.iter() {
-            let mut left_cars:Vec<u32> = vec![];
-                cars.iter().for_each(|c| {
+        let mut map: BTreeMap<Route, Vec<u32>> = BTreeMap::new();
+        for (route, cars) in self.tracks.iter() {
+            let mut left_cars: Vec<u32> = vec![];
+            cars.iter().for_each(|c| {
                 if self.cars.contains_key(c) {
                     left_cars.push(*c);
                 }
             });
-            if left_cars.len() > 0 {
+            if !left_cars.is_empty() {
                 map.insert(*route, left_cars);
             }
         }
@@ -523,39 +972,74 @@ impl Intersection {
     }
 }
 
-fn generate_route(routes: Vec<Route>) -> Route {
-    let n: usize = rand::gen_range(0, routes.len());
-    return routes[n];
+fn default_controller() -> Box<dyn Controller> {
+    Box::new(QueueController::new())
+}
+
+fn generate_route(rng: &mut ChaCha12Rng, routes: Vec<Route>) -> Route {
+    let n: usize = rng.gen_range(0..routes.len());
+    routes[n]
 }
 
-fn intersect(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> bool {
-    return (
-        (
-            (
-                (a.x >= b.x && a.x <= d.x) || (c.x >= b.x && c.x <= d.x)
-            ) && (
-                (a.y >= b.y && a.y <= d.y) || (c.y >= b.y && c.y <= d.y)
-            )
-        ) || (
-            (
-                (b.x >= a.x && b.x <= c.x) || (d.x >= a.x && d.x <= c.x)
-            ) && (
-                (b.y >= a.y && b.y <= c.y) || (d.y >= a.y && d.y <= c.y)
-            )
-        )
-    ) || (
-        (
-            (
-                (a.x >= b.x && a.x <= d.x) || (c.x >= b.x && c.x <= d.x)
-            ) && (
-                (b.y >= a.y && b.y <= c.y) || (d.y >= a.y && d.y <= c.y)
-            )
-        ) || (
-            (
-                (b.x >= a.x && b.x <= c.x) || (d.x >= a.x && d.x <= c.x)
-            ) && (
-                (a.y >= b.y && a.y <= d.y) || (c.y >= b.y && c.y <= d.y)
-            )
-        )
-    );
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_same, Hold};
+
+    fn run(seed: u64) -> Intersection {
+        let mut intersection = Intersection::with_seed(seed);
+        for tick in 0..1800 {
+            if tick % 20 == 0 {
+                intersection.add_car(Route::ALL.to_vec());
+            }
+            intersection.step(TIME_STEP);
+        }
+        intersection
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let (a, b) = (run(11), run(11));
+        assert!(a.number_of_passed_vehicles > 0);
+        assert_same(&a, &b);
+    }
+
+    #[test]
+    fn headway_follower_keeps_its_distance_to_a_stopped_leader() {
+        let mut intersection = Intersection::with_seed(1);
+        intersection.set_controller(Box::new(Hold));
+        intersection.safe_distance = SafeDistance::Headway { min_gap: 5.0, headway: 0.5 };
+        intersection.deadlock_ticks = u32::MAX;
+        for tick in 0..1200 {
+            if tick % 120 == 0 {
+                intersection.spawn(Route::N_S);
+            }
+            intersection.step(TIME_STEP);
+        }
+        assert!(intersection.cars().count() > 2);
+        assert!(intersection.cars().all(|car| car.speed == 0.0));
+        assert_eq!(intersection.collapse, 0);
+        assert_eq!(intersection.close_call_events(), &[]);
+    }
+
+    #[test]
+    fn deadlocks_are_broken_one_car_at_a_time() {
+        let mut intersection = Intersection::with_seed(1);
+        intersection.set_controller(Box::new(Hold));
+        intersection.deadlock_ticks = 120;
+        let routes = [Route::N_S, Route::W_E, Route::S_N, Route::E_W];
+        let ids: Vec<u32> = routes.iter().map(|route| intersection.spawn(*route).unwrap()).collect();
+        let mut most_forced = 0;
+        for _ in 0..3600 {
+            intersection.step(TIME_STEP);
+            most_forced = most_forced.max(intersection.forced.len());
+        }
+        let forced: Vec<Option<u32>> = intersection.deadlocks().iter().map(|d| d.forced).collect();
+        assert_eq!(forced, ids.iter().map(|id| Some(*id)).collect::<Vec<_>>());
+        assert_eq!(intersection.deadlocks()[0].cars.len(), 4);
+        assert_eq!(most_forced, 1);
+        assert!(intersection.forced.is_empty());
+        assert_eq!(intersection.number_of_passed_vehicles, 4);
+        assert_eq!(intersection.collapse, 0);
+    }
+}
@@ -1,36 +1,27 @@
-use macroquad::prelude::*;
-// pub use rand::Rng;
-use r::Rng; // 0.8.5
+#![allow(non_snake_case)]
 
+use macroquad::prelude::*;
 
 use Smart_Road::*;
-// use raster::{Color, Image};
+use Smart_Road::controller;
+use Smart_Road::layout::IntersectionLayout;
+use Smart_Road::replay::{Recording, Replay};
+use Smart_Road::report::Report;
+use Smart_Road::scenario::{Scenario, ScenarioPlayer};
+use Smart_Road::snapshot::Snapshot;
+use Smart_Road::traffic::{Demand, TrafficGenerator};
+use Smart_Road::vehicle::VehicleMix;
 
 pub mod draw;
 
-use crate::draw::road;
-
-// const CAR_HEIGHT: f32 = 10f32;
-// const CAR_LENGTH: f32 = 30f32;
-const CAR_SPEED: f32 = 50f32;
-const CAR_PADDING: f32 = 20f32;
-
-//Car Starting positions:
-const START_FROM_TOP_TO_LEFT: macroquad::math::Vec2 = vec2(370f32, 0f32 - CAR_LENGTH);
-const START_FROM_TOP_FORWARD: macroquad::math::Vec2 = vec2(340f32, 0f32 - CAR_LENGTH);
-const START_FROM_TOP_TO_RIGHT: macroquad::math::Vec2 = vec2(310f32, 0f32 - CAR_LENGTH);
-/**/
-const START_FROM_BOTTOM_TO_LEFT: macroquad::math::Vec2 = vec2(400f32, 800f32);
-const START_FROM_BOTTOM_TO_FORWARD: macroquad::math::Vec2 = vec2(430f32, 800f32);
-const START_FROM_BOTTOM_TO_RIGHT: macroquad::math::Vec2 = vec2(460f32, 800f32);
-/**/
-const START_FROM_LEFT_TO_LEFT: macroquad::math::Vec2 = vec2(0f32 - CAR_LENGTH, 400f32);
-const START_FROM_LEFT_FORWARD: macroquad::math::Vec2 = vec2(0f32 - CAR_LENGTH, 430f32);
-const START_FROM_LEFT_TO_RIGHT: macroquad::math::Vec2 = vec2(0f32 - CAR_LENGTH, 460f32);
-/**/
-const START_FROM_RIGHT_TO_LEFT: macroquad::math::Vec2 = vec2(800f32, 370f32);
-const START_FROM_RIGHT_FORWARD: macroquad::math::Vec2 = vec2(800f32, 340f32);
-const START_FROM_RIGHT_TO_RIGHT: macroquad::math::Vec2 = vec2(800f32, 310f32);
+use crate::draw::{cars, road};
+
+// Upper bound on simulation ticks per frame, so a long stall does not freeze the window.
+const MAX_STEPS_PER_FRAME: u32 = 10;
+// Factor the `+` and `-` keys change the generator's arrival rates by.
+const DEMAND_STEP: f32 = 1.25;
+// How far LEFT and RIGHT jump in replay mode.
+const SCRUB_SECONDS: f32 = 5.0;
 
 pub fn draw_title_text(text: &str) {
     let dims = measure_text(text, Default::default(), 50u16, 1.0f32);
@@ -41,27 +32,163 @@ pub fn draw_title_text(text: &str) {
         TextParams { font: Default::default(), font_size: 50u16, color: WHITE, ..Default::default() })
 }
 
+pub fn draw_lines_text(lines: &[String]) {
+    let font_size = 30u16;
+    let line_height = font_size as f32 * 1.2;
+    let top = screen_height() * 0.5f32 - line_height * lines.len() as f32 * 0.5f32;
+    for (i, line) in lines.iter().enumerate() {
+        let dims = measure_text(line, Default::default(), font_size, 1.0f32);
+        draw_text_ex(
+            line,
+            screen_width() * 0.5f32 - dims.width * 0.5f32,
+            top + line_height * (i + 1) as f32,
+            TextParams { font: Default::default(), font_size, color: WHITE, ..Default::default() })
+    }
+}
+
+const STATISTICS_JSON: &str = "statistics.json";
+const RECORDING_JSON: &str = "recording.json";
+const STATE_JSON: &str = "state.json";
+const STATISTICS_CSV: &str = "statistics.csv";
+
 pub struct Statistics {
-    passed_intersection: u32,
+    report: Report,
 }
 
 impl Statistics {
-    pub fn new() -> Self {
-        Self { passed_intersection: 0 }
+    pub fn new(intersection: &Intersection) -> Self {
+        Self { report: Report::new(intersection) }
+    }
+
+    pub fn update(&mut self, intersection: &Intersection) {
+        self.report = Report::new(intersection);
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let stats = &self.report.aggregates;
+        let mut lines = vec![
+            "STATISTICS".to_string(),
+            format!("cars finished: {}", stats.passed_vehicles),
+            format!("max velocity: {:.0} px/s", stats.max_velocity),
+            match stats.min_velocity {
+                Some(min) => format!("min velocity: {:.0} px/s", min),
+                None => "min velocity: -".to_string(),
+            },
+            format!("collisions: {}", stats.collisions),
+            format!("close calls: {}", stats.close_calls),
+            format!("deadlocks: {}", stats.deadlocks),
+        ];
+        if let (Some(max), Some(min)) = (stats.crossing_time_max, stats.crossing_time_min) {
+            lines.push(format!("max time to cross: {:.2} s", max));
+            lines.push(format!("min time to cross: {:.2} s", min));
+        }
+        if let (Some(max), Some(min)) = (stats.travel_time_max, stats.travel_time_min) {
+            lines.push(format!("max time to pass: {:.2} s", max));
+            lines.push(format!("min time to pass: {:.2} s", min));
+        }
+        lines
+    }
+
+    pub fn export(&self) {
+        let result = self.report.write_json(STATISTICS_JSON)
+            .and_then(|_| self.report.write_csv(STATISTICS_CSV));
+        match result {
+            Ok(()) => println!("statistics written to {} and {} (plus its summary, collisions and close calls)", STATISTICS_JSON, STATISTICS_CSV),
+            Err(e) => eprintln!("failed to write statistics: {}", e),
+        }
     }
 }
 
 
+fn save_recording(intersection: &Intersection) {
+    match intersection.recording().save(RECORDING_JSON) {
+        Ok(()) => println!("recording written to {}, replay it with --replay {}", RECORDING_JSON, RECORDING_JSON),
+        Err(e) => eprintln!("failed to write recording: {}", e),
+    }
+}
+
+/// One frame of replay mode: SPACE pauses, LEFT and RIGHT jump back and forth by
+/// `SCRUB_SECONDS`, PERIOD steps a single tick while paused. Returns true on ESCAPE.
+fn replay_frame(replay: &mut Replay, paused: &mut bool, accumulator: &mut f32) -> bool {
+    road(replay.intersection().layout(), replay.intersection().controller());
+    if *paused {
+        *accumulator = 0.0;
+    } else {
+        *accumulator += get_frame_time();
+        let mut steps = 0;
+        while *accumulator >= TIME_STEP && steps < MAX_STEPS_PER_FRAME && !replay.finished() {
+            replay.step();
+            *accumulator -= TIME_STEP;
+            steps += 1;
+        }
+        if steps == MAX_STEPS_PER_FRAME || replay.finished() {
+            *accumulator = 0.0;
+        }
+    }
+    if is_key_pressed(KeyCode::Space) {
+        *paused = !*paused;
+    }
+    if *paused && is_key_pressed(KeyCode::Period) && !replay.finished() {
+        replay.step();
+    }
+    let scrub = (SCRUB_SECONDS / TIME_STEP).round() as u64;
+    if is_key_pressed(KeyCode::Right) {
+        replay.seek(replay.tick() + scrub);
+    }
+    if is_key_pressed(KeyCode::Left) {
+        replay.seek(replay.tick().saturating_sub(scrub));
+    }
+    cars(replay.intersection());
+    let total = replay.recording().ticks as f32 * TIME_STEP;
+    let state = if *paused { " (paused)" } else { "" };
+    draw_text(&format!("REPLAY {:.1} / {:.1} s{}", replay.intersection().time(), total, state), 10.0, 20.0, 20.0, WHITE);
+    is_key_pressed(KeyCode::Escape)
+}
+
 pub enum GameState {
     Menu,
     Game,
     Statistics,
 }
 
+/// Reads `<name> <value>` from the command line, e.g. `--seed 42`.
+fn arg<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|a| a == name)?;
+    args.get(i + 1)?.parse().ok()
+}
+
+/// Recording from `--replay <file>`, if any.
+fn recording_from_args() -> Option<Recording> {
+    arg::<String>("--replay").map(|path| Recording::load(&path).unwrap_or_else(|e| {
+        eprintln!("failed to load recording {}: {}", path, e);
+        std::process::exit(1);
+    }))
+}
+
+/// Layout of the replayed recording or loaded state, from `--layout <file>`, or the default one.
+fn layout_from_args() -> IntersectionLayout {
+    if let Some(recording) = recording_from_args() {
+        return recording.layout;
+    }
+    if let Some(snapshot) = arg::<String>("--load-state").and_then(|path| Snapshot::load(path).ok()) {
+        return *snapshot.intersection.layout();
+    }
+    match arg::<String>("--layout") {
+        Some(path) => IntersectionLayout::load(&path).unwrap_or_else(|e| {
+            eprintln!("failed to load layout {}: {}", path, e);
+            std::process::exit(1);
+        }),
+        None => IntersectionLayout::default(),
+    }
+}
+
 fn window_conf() -> Conf {
+    let layout = layout_from_args();
     Conf {
         window_title: "SMART ROAD".to_owned(),
-        window_height: 800,
+        window_width: layout.canvas_width as i32,
+        window_height: layout.canvas_height as i32,
         window_resizable: false,
         ..Default::default()
     }
@@ -70,8 +197,61 @@ fn window_conf() -> Conf {
 #[macroquad::main(window_conf)]
 async fn main() {
     let mut game_state = GameState::Menu;
-    let mut statistics = Statistics::new();
-    let mut intersection = Intersection::new();
+    // scripted spawns from `--scenario <file>`, on top of whatever the keys add
+    let scenario = arg::<String>("--scenario").map(|path| Scenario::load(&path).unwrap_or_else(|e| {
+        eprintln!("failed to load scenario {}: {}", path, e);
+        std::process::exit(1);
+    }));
+    let seed = arg("--seed")
+        .or(scenario.as_ref().and_then(|s| s.seed))
+        .unwrap_or_else(r::random);
+    let mut intersection = Intersection::with_layout(seed, layout_from_args());
+    let controller_name = arg::<String>("--controller")
+        .or(scenario.as_ref().and_then(|s| s.controller.clone()));
+    if let Some(name) = controller_name {
+        match controller::by_name(&name, intersection.layout()) {
+            Some(controller) => intersection.set_controller(controller),
+            None => {
+                eprintln!("unknown controller {}, expected one of {:?}", name, controller::CONTROLLERS);
+                std::process::exit(1);
+            }
+        }
+    }
+    // trucks, buses and emergency vehicles in the shares of `--mix <file>`
+    if let Some(path) = arg::<String>("--mix") {
+        intersection.vehicle_mix = VehicleMix::load(&path).unwrap_or_else(|e| {
+            eprintln!("failed to load vehicle mix {}: {}", path, e);
+            std::process::exit(1);
+        });
+    }
+    // continue from a state saved with S
+    if let Some(path) = arg::<String>("--load-state") {
+        intersection = Snapshot::load(&path).and_then(Snapshot::restore).unwrap_or_else(|e| {
+            eprintln!("failed to load state {}: {}", path, e);
+            std::process::exit(1);
+        });
+    }
+    println!("controller: {}", intersection.controller().name());
+    println!("seed: {}", intersection.seed());
+    // Poisson traffic, on from the start with `--demand <file>`, toggled with G
+    let demand = arg::<String>("--demand").map(|path| Demand::load(&path).unwrap_or_else(|e| {
+        eprintln!("failed to load demand {}: {}", path, e);
+        std::process::exit(1);
+    }));
+    let mut generator = TrafficGenerator::new(demand.unwrap_or_default(), seed);
+    let mut generating = demand.is_some();
+    let mut player = scenario.map(ScenarioPlayer::new);
+    let mut statistics = Statistics::new(&intersection);
+    let mut accumulator: f32 = 0.0;
+    let mut replay = recording_from_args().map(|recording| Replay::new(recording).unwrap_or_else(|e| {
+        eprintln!("failed to replay: {}", e);
+        std::process::exit(1);
+    }));
+    if let Some(replay) = &replay {
+        let recording = replay.recording();
+        println!("replaying seed {} with {}, {} ticks", recording.seed, recording.controller, recording.ticks);
+    }
+    let mut paused = false;
 
     loop {
         match game_state {
@@ -81,12 +261,33 @@ async fn main() {
                     game_state = GameState::Game;
                 }
             }
+            GameState::Game if replay.is_some() => {
+                let replay = replay.as_mut().unwrap();
+                if replay_frame(replay, &mut paused, &mut accumulator) {
+                    statistics.update(replay.intersection());
+                    game_state = GameState::Statistics
+                }
+            }
             GameState::Game => {
                 //draw road
-                road();
-                intersection.drive_cars();
-                intersection.remove_cars();
-                intersection.draw_cars();
+                road(intersection.layout(), intersection.controller());
+                accumulator += get_frame_time();
+                let mut steps = 0;
+                while accumulator >= TIME_STEP && steps < MAX_STEPS_PER_FRAME {
+                    if generating {
+                        generator.update(&mut intersection);
+                    }
+                    if let Some(player) = player.as_mut() {
+                        player.update(&mut intersection);
+                    }
+                    intersection.step(TIME_STEP);
+                    accumulator -= TIME_STEP;
+                    steps += 1;
+                }
+                if steps == MAX_STEPS_PER_FRAME {
+                    accumulator = 0.0;
+                }
+                cars(&intersection);
 
                 //Draw new car with direction from right to left
                 if is_key_pressed(KeyCode::Left) {
@@ -114,20 +315,48 @@ async fn main() {
 
                 //Draw new car with a random direction
                 if is_key_pressed(KeyCode::R) {
-                    let routes = vec![Route::E_W, Route::W_E, Route::S_N, Route::N_S, Route::E_N, Route::W_S, Route::N_W, Route::S_E, Route::N_E, Route::S_W, Route::W_N, Route::E_S];
-                    // let routes = vec![ Route::N_W, Route::N_E, Route::S_W];
-                    intersection.add_car(routes);
+                    intersection.add_car(Route::ALL.to_vec());
+                }
+
+                //switch the traffic generator on or off
+                if is_key_pressed(KeyCode::G) {
+                    generating = !generating;
+                    println!("traffic generator {}", if generating { "on" } else { "off" });
+                }
+
+                //more or less generated traffic
+                if is_key_pressed(KeyCode::Equal) || is_key_pressed(KeyCode::Minus) {
+                    let factor = if is_key_pressed(KeyCode::Equal) { DEMAND_STEP } else { 1.0 / DEMAND_STEP };
+                    generator.set_demand(generator.demand().scaled(factor));
+                    println!("arrival rate north: {:.2} cars/s", generator.demand().north.rate);
+                }
+
+                //export statistics and the recording without stopping the simulation
+                if is_key_pressed(KeyCode::E) {
+                    statistics.update(&intersection);
+                    statistics.export();
+                    save_recording(&intersection);
+                }
+
+                //save the whole simulation state to continue from later with --load-state
+                if is_key_pressed(KeyCode::S) {
+                    match intersection.snapshot().save(STATE_JSON) {
+                        Ok(()) => println!("state written to {}", STATE_JSON),
+                        Err(e) => eprintln!("failed to write state: {}", e),
+                    }
                 }
 
                 //end of simulation
                 if is_key_pressed(KeyCode::Escape) {
+                    statistics.update(&intersection);
+                    statistics.export();
+                    save_recording(&intersection);
                     game_state = GameState::Statistics
                 }
             }
 
             GameState::Statistics => {
-                statistics.passed_intersection = intersection.number_of_passed_vehicles;
-                draw_title_text(&format!("STATISTICS: cars finished: {}", statistics.passed_intersection));
+                draw_lines_text(&statistics.lines());
             }
         }
 
@@ -0,0 +1,479 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::controller;
+use crate::layout::IntersectionLayout;
+use crate::vehicle::{VehicleKind, VehicleMix};
+use crate::{Direction, Intersection, Route, CAR_SPEED_NORMAL};
+
+const DIRECTIONS: [Direction; 4] = [Direction::Down, Direction::Up, Direction::Right, Direction::Left];
+// Seconds a vehicle queued on an approach is expected to add to the wait there,
+// when planning around congestion.
+const QUEUE_DELAY: f32 = 2.0;
+
+/// One-way road from intersection `from` to intersection `to`. Cars leaving `from`
+/// moving in `direction` arrive at `to` on an approach moving in the same direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Road {
+    pub from: usize,
+    pub to: usize,
+    pub direction: Direction,
+    /// Length in pixels between the two canvases.
+    pub length: f32,
+}
+
+/// A vehicle's way through the network: the route it takes at each intersection in turn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trip {
+    pub id: u32,
+    pub hops: Vec<(usize, Route)>,
+    /// Index into `hops` of the intersection the vehicle is at or heading to.
+    pub hop: usize,
+    pub departed_at: f32,
+    pub arrived_at: Option<f32>,
+    /// Where the trip leaves the network, as (intersection, direction of travel),
+    /// for trips planned by `plan_trip`.
+    pub destination: Option<(usize, Direction)>,
+    /// How often the rest of the trip was replanned on the way.
+    pub rerouted: u32,
+    /// Picked from the vehicle mix where the trip enters, then kept on every hop.
+    pub kind: Option<VehicleKind>,
+}
+
+/// A vehicle on a road, or waiting at the road's end until the next approach lane has room.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transit {
+    trip: u32,
+    arrives_at: f32,
+}
+
+/// Several intersections joined by roads. Every intersection simulates its own canvas;
+/// a car driving off one canvas onto a road travels it at normal speed and is spawned
+/// on the next intersection's approach for the next hop of its trip.
+#[derive(Debug, Clone)]
+pub struct RoadNetwork {
+    intersections: Vec<Intersection>,
+    roads: Vec<Road>,
+    time: f32,
+    trips: BTreeMap<u32, Trip>,
+    trip_id: u32,
+    // Which trip a car of an intersection belongs to, by (intersection, car id).
+    on_board: HashMap<(usize, u32), u32>,
+    // Cars of every intersection already handed on.
+    handled: Vec<usize>,
+    // Per road, vehicles in order of arrival.
+    transit: Vec<VecDeque<Transit>>,
+    // Per intersection and lane, i.e. route, trips that could not enter yet.
+    waiting: BTreeMap<(usize, Route), VecDeque<u32>>,
+    finished: Vec<Trip>,
+    /// Replan planned trips around queues each time they are handed on to a road.
+    pub reroute: bool,
+}
+
+impl RoadNetwork {
+    pub fn new(intersections: Vec<Intersection>) -> RoadNetwork {
+        let n = intersections.len();
+        RoadNetwork {
+            intersections,
+            roads: Vec::new(),
+            time: 0.0,
+            trips: BTreeMap::new(),
+            trip_id: 0,
+            on_board: HashMap::new(),
+            handled: vec![0; n],
+            transit: Vec::new(),
+            waiting: BTreeMap::new(),
+            finished: Vec::new(),
+            reroute: false,
+        }
+    }
+
+    /// A `columns` by `rows` grid of identical intersections, numbered row by row from
+    /// the north-west corner, with roads of `road_length` both ways between neighbours.
+    pub fn grid(columns: usize, rows: usize, layout: IntersectionLayout, controller: &str, seed: u64, road_length: f32) -> Option<RoadNetwork> {
+        let mut intersections = vec![];
+        for i in 0..columns * rows {
+            let mut intersection = Intersection::with_layout(seed.wrapping_add(i as u64), layout);
+            intersection.set_controller(controller::by_name(controller, &layout)?);
+            intersections.push(intersection);
+        }
+        let mut network = RoadNetwork::new(intersections);
+        for row in 0..rows {
+            for column in 0..columns {
+                let i = row * columns + column;
+                if column + 1 < columns {
+                    network.connect(i, i + 1, Direction::Right, road_length);
+                    network.connect(i + 1, i, Direction::Left, road_length);
+                }
+                if row + 1 < rows {
+                    network.connect(i, i + columns, Direction::Down, road_length);
+                    network.connect(i + columns, i, Direction::Up, road_length);
+                }
+            }
+        }
+        Some(network)
+    }
+
+    /// Adds a one-way road; cars leaving `from` moving in `direction` continue to `to`.
+    pub fn connect(&mut self, from: usize, to: usize, direction: Direction, length: f32) {
+        self.roads.push(Road { from, to, direction, length });
+        self.transit.push(VecDeque::new());
+    }
+
+    pub fn intersections(&self) -> &[Intersection] {
+        &self.intersections
+    }
+
+    /// Spawns vehicles of every intersection in the shares of `mix`.
+    pub fn set_vehicle_mix(&mut self, mix: VehicleMix) {
+        for intersection in self.intersections.iter_mut() {
+            intersection.vehicle_mix = mix;
+        }
+    }
+
+    pub fn roads(&self) -> &[Road] {
+        &self.roads
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    fn road(&self, from: usize, direction: Direction) -> Option<usize> {
+        self.roads.iter().position(|r| r.from == from && r.direction == direction)
+    }
+
+    /// Intersection reached by leaving `from` in `direction`, if a road goes there.
+    pub fn next_intersection(&self, from: usize, direction: Direction) -> Option<usize> {
+        self.road(from, direction).map(|r| self.roads[r].to)
+    }
+
+    /// Approaches no road leads to, where traffic enters the network from outside,
+    /// as (intersection, direction of travel).
+    pub fn entries(&self) -> Vec<(usize, Direction)> {
+        (0..self.intersections.len())
+            .flat_map(|i| DIRECTIONS.into_iter().map(move |d| (i, d)))
+            .filter(|(i, d)| !self.roads.iter().any(|r| r.to == *i && r.direction == *d))
+            .collect()
+    }
+
+    /// Exits with no road beyond them, where traffic leaves the network, as
+    /// (intersection, direction of travel).
+    pub fn exits(&self) -> Vec<(usize, Direction)> {
+        (0..self.intersections.len())
+            .flat_map(|i| DIRECTIONS.into_iter().map(move |d| (i, d)))
+            .filter(|(i, d)| self.road(*i, *d).is_none())
+            .collect()
+    }
+
+    /// Fastest hops from approaching `at` moving in `direction` to leaving the network
+    /// at `destination`, or `None` if it cannot be reached (there are no U-turns) or
+    /// either end is not an intersection of the network.
+    /// Times assume normal speed; with `congestion` every vehicle queued on an approach
+    /// adds to the time it takes to get through that intersection.
+    pub fn plan(&self, at: usize, direction: Direction, destination: (usize, Direction), congestion: bool) -> Option<Vec<(usize, Route)>> {
+        // Dijkstra over (intersection, direction of travel on its approach).
+        let node = |i: usize, d: Direction| i * 4 + DIRECTIONS.iter().position(|x| *x == d).unwrap();
+        let n = self.intersections.len() * 4;
+        if at >= self.intersections.len() || destination.0 >= self.intersections.len() {
+            return None;
+        }
+        let mut time = vec![f32::INFINITY; n];
+        let mut previous: Vec<Option<(usize, Route)>> = vec![None; n];
+        let mut done = vec![false; n];
+        let start = node(at, direction);
+        time[start] = 0.0;
+        let mut best: Option<(f32, usize, Route)> = None;
+        while let Some(current) = (0..n).filter(|&v| !done[v] && time[v].is_finite())
+            .min_by(|&a, &b| time[a].total_cmp(&time[b]))
+        {
+            if best.is_some_and(|(t, _, _)| t <= time[current]) {
+                break;
+            }
+            done[current] = true;
+            let (i, approach) = (current / 4, DIRECTIONS[current % 4]);
+            let mut wait = 0.0;
+            if congestion {
+                wait = self.queue(i, approach) as f32 * QUEUE_DELAY;
+            }
+            for route in Route::ALL.iter().copied().filter(|r| r.get_direction() == approach) {
+                let crossed = time[current] + wait + self.intersections[i].layout().path(route).length() / CAR_SPEED_NORMAL;
+                let exit = route.exit_direction();
+                if (i, exit) == destination && best.is_none_or(|(t, _, _)| crossed < t) {
+                    best = Some((crossed, current, route));
+                }
+                if let Some(r) = self.road(i, exit) {
+                    let next = node(self.roads[r].to, exit);
+                    let arrival = crossed + self.roads[r].length / CAR_SPEED_NORMAL;
+                    if arrival < time[next] {
+                        time[next] = arrival;
+                        previous[next] = Some((current, route));
+                    }
+                }
+            }
+        }
+
+        let (_, mut current, route) = best?;
+        let mut hops = vec![(current / 4, route)];
+        while let Some((before, route)) = previous[current] {
+            hops.push((before / 4, route));
+            current = before;
+        }
+        hops.reverse();
+        Some(hops)
+    }
+
+    /// Vehicles queued for approach `direction` of intersection `i`: on the road leading
+    /// there, waiting to enter and on the approach lanes short of the crossing.
+    fn queue(&self, i: usize, direction: Direction) -> usize {
+        let on_road = self.roads.iter().enumerate()
+            .filter(|(_, r)| r.to == i && r.direction == direction)
+            .map(|(r, _)| self.transit[r].len())
+            .sum::<usize>();
+        let waiting = self.waiting.iter()
+            .filter(|((at, route), _)| *at == i && route.get_direction() == direction)
+            .map(|(_, trips)| trips.len())
+            .sum::<usize>();
+        let approaching = self.intersections[i].cars()
+            .filter(|c| c.route.get_direction() == direction && c.entered_at.is_none())
+            .count();
+        on_road + waiting + approaching
+    }
+
+    /// Starts a trip entering at `at` moving in `direction` along the fastest way to
+    /// `destination`.
+    pub fn plan_trip(&mut self, at: usize, direction: Direction, destination: (usize, Direction)) -> Result<u32, String> {
+        let hops = self.plan(at, direction, destination, self.reroute).ok_or_else(|| {
+            format!("no way from {} going {:?} to leave {} going {:?}", at, direction, destination.0, destination.1)
+        })?;
+        let id = self.add_trip(hops)?;
+        self.trips.get_mut(&id).unwrap().destination = Some(destination);
+        Ok(id)
+    }
+
+    /// Starts a trip along `hops`. Every hop after the first has to be reached by the
+    /// road the previous one exits on and has to approach in that road's direction.
+    pub fn add_trip(&mut self, hops: Vec<(usize, Route)>) -> Result<u32, String> {
+        let Some(&(first, _)) = hops.first() else {
+            return Err("a trip needs at least one hop".to_string());
+        };
+        if first >= self.intersections.len() {
+            return Err(format!("no intersection {}", first));
+        }
+        for pair in hops.windows(2) {
+            let ((from, route), (to, next_route)) = (pair[0], pair[1]);
+            let direction = route.exit_direction();
+            if self.next_intersection(from, direction) != Some(to) {
+                return Err(format!("no road from {} going {:?} to {}", from, direction, to));
+            }
+            if next_route.get_direction() != direction {
+                return Err(format!("{:?} at {} does not continue {:?}", next_route, to, direction));
+            }
+        }
+        self.trip_id += 1;
+        let id = self.trip_id;
+        self.trips.insert(id, Trip {
+            id,
+            hops,
+            hop: 0,
+            departed_at: self.time,
+            arrived_at: None,
+            destination: None,
+            rerouted: 0,
+            kind: None,
+        });
+        self.wait(id);
+        Ok(id)
+    }
+
+    /// Trips that have left the network, in the order they left.
+    pub fn finished_trips(&self) -> &[Trip] {
+        &self.finished
+    }
+
+    /// Trips still on their way, including those waiting to enter.
+    pub fn active_trips(&self) -> impl Iterator<Item = &Trip> {
+        self.trips.values()
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        self.time += dt;
+        self.arrive();
+        for intersection in self.intersections.iter_mut() {
+            intersection.step(dt);
+        }
+        self.hand_on();
+    }
+
+    /// Queues a trip for the lane of its current hop.
+    fn wait(&mut self, id: u32) {
+        let trip = &self.trips[&id];
+        self.waiting.entry(trip.hops[trip.hop]).or_default().push_back(id);
+    }
+
+    /// Spawns waiting trips at their current intersection, oldest first per lane. A full
+    /// lane only holds up the trips queued for it.
+    fn arrive(&mut self) {
+        for r in 0..self.roads.len() {
+            while self.transit[r].front().is_some_and(|t| t.arrives_at <= self.time) {
+                let transit = self.transit[r].pop_front().unwrap();
+                self.wait(transit.trip);
+            }
+        }
+        for (&(i, route), trips) in self.waiting.iter_mut() {
+            while let Some(&id) = trips.front() {
+                let trip = self.trips.get_mut(&id).unwrap();
+                let intersection = &mut self.intersections[i];
+                let spawned = match trip.kind {
+                    Some(kind) => intersection.spawn_kind(route, kind),
+                    None => intersection.spawn(route),
+                };
+                match spawned {
+                    Some(car_id) => {
+                        trip.kind = intersection.car(car_id).map(|car| car.kind);
+                        self.on_board.insert((i, car_id), id);
+                        trips.pop_front();
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Replaces the rest of a planned trip with the currently fastest way, if it differs.
+    fn replan(&mut self, id: u32, at: usize, direction: Direction) {
+        let trip = &self.trips[&id];
+        let Some(destination) = trip.destination else {
+            return;
+        };
+        let Some(hops) = self.plan(at, direction, destination, true) else {
+            return;
+        };
+        let trip = self.trips.get_mut(&id).unwrap();
+        if trip.hops[trip.hop..] != hops[..] {
+            trip.hops.truncate(trip.hop);
+            trip.hops.extend(hops);
+            trip.rerouted += 1;
+        }
+    }
+
+    /// Moves cars that drove off an intersection onto the road of their next hop.
+    fn hand_on(&mut self) {
+        for i in 0..self.intersections.len() {
+            let left: Vec<(u32, Route)> = self.intersections[i].finished_cars()[self.handled[i]..]
+                .iter()
+                .map(|t| (t.id, t.route))
+                .collect();
+            self.handled[i] += left.len();
+            for (car_id, route) in left {
+                let Some(id) = self.on_board.remove(&(i, car_id)) else {
+                    continue;
+                };
+                let trip = self.trips.get_mut(&id).unwrap();
+                trip.hop += 1;
+                if trip.hop == trip.hops.len() {
+                    trip.arrived_at = Some(self.time);
+                    self.finished.push(self.trips.remove(&id).unwrap());
+                    continue;
+                }
+                let direction = route.exit_direction();
+                let r = self.road(i, direction).expect("trip was checked against the roads");
+                let arrives_at = self.time + self.roads[r].length / CAR_SPEED_NORMAL;
+                self.transit[r].push_back(Transit { trip: id, arrives_at });
+                if self.reroute {
+                    self.replan(id, self.roads[r].to, direction);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TIME_STEP;
+
+    fn grid(columns: usize, rows: usize) -> RoadNetwork {
+        RoadNetwork::grid(columns, rows, IntersectionLayout::default(), "queue", 1, 200.0).unwrap()
+    }
+
+    #[test]
+    fn plans_straight_through_a_row() {
+        let network = grid(3, 1);
+        let hops = network.plan(0, Direction::Right, (2, Direction::Right), false);
+        assert_eq!(hops, Some(vec![(0, Route::W_E), (1, Route::W_E), (2, Route::W_E)]));
+    }
+
+    #[test]
+    fn plans_the_shorter_way_round() {
+        // leaving 1 southwards from its west approach: turning right at 1 beats going on to 2 and back
+        let network = grid(3, 1);
+        let hops = network.plan(0, Direction::Right, (1, Direction::Down), false).unwrap();
+        assert_eq!(hops, vec![(0, Route::W_E), (1, Route::W_S)]);
+    }
+
+    #[test]
+    fn no_plan_without_u_turns() {
+        let network = grid(1, 1);
+        assert_eq!(network.plan(0, Direction::Down, (0, Direction::Up), false), None);
+    }
+
+    #[test]
+    fn no_plan_for_unknown_intersections() {
+        let network = grid(3, 3);
+        assert_eq!(network.plan(12, Direction::Down, (8, Direction::Down), false), None);
+        assert_eq!(network.plan(0, Direction::Down, (12, Direction::Down), false), None);
+    }
+
+    #[test]
+    fn congestion_changes_the_plan() {
+        // 0 1
+        // 2 3: from west of 0 to south of 3, either via 1 or via 2
+        let mut network = grid(2, 2);
+        let destination = (3, Direction::Down);
+        let free = network.plan(0, Direction::Right, destination, false).unwrap();
+        let (via, route) = free[1];
+        // fill the approach the free plan uses at its second intersection
+        for _ in 0..30 {
+            network.add_trip(vec![(via, route)]).unwrap();
+        }
+        assert_eq!(network.plan(0, Direction::Right, destination, false).unwrap(), free);
+        let congested = network.plan(0, Direction::Right, destination, true).unwrap();
+        assert_ne!(congested[1].0, via);
+        assert_eq!(congested.last().unwrap().1.exit_direction(), Direction::Down);
+    }
+
+    #[test]
+    fn hands_a_trip_on_to_the_next_intersection() {
+        let mut network = grid(2, 1);
+        let id = network.add_trip(vec![(0, Route::W_E), (1, Route::W_E)]).unwrap();
+        let mut handed_on = false;
+        while network.finished_trips().is_empty() && network.time() < 60.0 {
+            network.step(TIME_STEP);
+            handed_on = handed_on || network.transit[0].iter().any(|t| t.trip == id);
+        }
+        assert!(handed_on);
+        let trip = &network.finished_trips()[0];
+        assert_eq!((trip.id, trip.hops.len(), trip.hop), (id, 2, 2));
+        // driving the road between the canvases takes its time too
+        assert!(trip.arrived_at.unwrap() - trip.departed_at > 200.0 / CAR_SPEED_NORMAL);
+        for intersection in network.intersections() {
+            let routes: Vec<Route> = intersection.finished_cars().iter().map(|t| t.route).collect();
+            assert_eq!(routes, vec![Route::W_E]);
+            assert_eq!(intersection.finished_cars()[0].kind, trip.kind.unwrap());
+        }
+    }
+
+    #[test]
+    fn a_full_lane_does_not_hold_back_the_others() {
+        let mut network = grid(2, 1);
+        for _ in 0..10 {
+            network.add_trip(vec![(1, Route::W_E)]).unwrap();
+        }
+        let turning = network.add_trip(vec![(1, Route::W_N)]).unwrap();
+        network.step(TIME_STEP);
+        assert!(network.waiting[&(1, Route::W_E)].len() >= 9);
+        assert!(network.waiting[&(1, Route::W_N)].is_empty());
+        assert!(network.on_board.values().any(|id| *id == turning));
+        assert_eq!(network.intersections()[1].cars().filter(|car| car.route == Route::W_N).count(), 1);
+    }
+}
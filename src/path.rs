@@ -0,0 +1,89 @@
+use std::f32::consts::FRAC_PI_2;
+
+use macroquad::math::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot;
+
+/// Centre line a car follows through the intersection: a straight approach, a quarter
+/// circle inside the crossing box for turns, and a straight exit off the canvas.
+/// Positions along the path are distances in pixels from the spawn point.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Path {
+    #[serde(with = "snapshot::vec2")]
+    start: Vec2,
+    /// Unit vectors of the approach and exit directions.
+    #[serde(with = "snapshot::vec2")]
+    approach: Vec2,
+    #[serde(with = "snapshot::vec2")]
+    exit: Vec2,
+    /// Radius of the turning arc, 0 for straight routes.
+    radius: f32,
+    box_entry: f32,
+    box_exit: f32,
+    length: f32,
+}
+
+impl Path {
+    /// Builds a path that reaches the crossing box after `box_entry`. A turn starts
+    /// right at the box edge with the given `radius`; a straight path crosses `box_size`.
+    /// The exit runs on for `exit_length` after the box.
+    pub fn new(start: Vec2, approach: Vec2, exit: Vec2, box_entry: f32, radius: f32, box_size: f32, exit_length: f32) -> Path {
+        let box_exit = if radius > 0.0 { box_entry + radius * FRAC_PI_2 } else { box_entry + box_size };
+        Path {
+            start,
+            approach,
+            exit,
+            radius,
+            box_entry,
+            box_exit,
+            length: box_exit + exit_length,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.length
+    }
+
+    /// Distance at which the path enters the crossing box.
+    pub fn box_entry(&self) -> f32 {
+        self.box_entry
+    }
+
+    /// Distance at which the path leaves the crossing box.
+    pub fn box_exit(&self) -> f32 {
+        self.box_exit
+    }
+
+    pub fn point(&self, distance: f32) -> Vec2 {
+        if self.radius == 0.0 || distance <= self.box_entry {
+            return self.start + self.approach * distance;
+        }
+        let arc_start = self.start + self.approach * self.box_entry;
+        if distance < self.box_exit {
+            let angle = (distance - self.box_entry) / self.radius;
+            let centre = arc_start + self.exit * self.radius;
+            return centre + (self.approach * angle.sin() - self.exit * angle.cos()) * self.radius;
+        }
+        let arc_end = arc_start + (self.approach + self.exit) * self.radius;
+        arc_end + self.exit * (distance - self.box_exit)
+    }
+
+    /// Unit vector of the direction of travel at `distance`.
+    pub fn tangent(&self, distance: f32) -> Vec2 {
+        if self.radius == 0.0 || distance <= self.box_entry {
+            self.approach
+        } else if distance < self.box_exit {
+            let angle = (distance - self.box_entry) / self.radius;
+            self.approach * angle.cos() + self.exit * angle.sin()
+        } else {
+            self.exit
+        }
+    }
+
+    /// Heading in radians at `distance`, measured clockwise from the x axis (screen coordinates).
+    pub fn heading(&self, distance: f32) -> f32 {
+        let tangent = self.tangent(distance);
+        tangent.y.atan2(tangent.x)
+    }
+}
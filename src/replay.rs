@@ -0,0 +1,176 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::controller;
+use crate::layout::IntersectionLayout;
+use crate::vehicle::{VehicleKind, VehicleMix};
+use crate::{Intersection, Route, SafeDistance, TIME_STEP};
+
+/// A request to spawn a car, made before simulating tick `tick`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Input {
+    /// `Intersection::add_car`: a random pick among `routes`.
+    AddCar { tick: u64, routes: Vec<Route> },
+    /// `Intersection::spawn`: exactly `route`, or `Intersection::spawn_kind` if `vehicle` is set.
+    Spawn {
+        tick: u64,
+        route: Route,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        vehicle: Option<VehicleKind>,
+    },
+}
+
+impl Input {
+    pub fn tick(&self) -> u64 {
+        match self {
+            Input::AddCar { tick, .. } | Input::Spawn { tick, .. } => *tick,
+        }
+    }
+}
+
+/// Everything needed to rerun a simulation tick for tick: how the intersection was
+/// set up and every car requested, in order. Runs are deterministic for a given seed,
+/// so this reproduces the run exactly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    pub seed: u64,
+    pub layout: IntersectionLayout,
+    pub controller: String,
+    pub safe_distance: SafeDistance,
+    pub deadlock_ticks: u32,
+    #[serde(default)]
+    pub vehicle_mix: VehicleMix,
+    /// Ticks simulated when the recording was taken.
+    pub ticks: u64,
+    pub inputs: Vec<Input>,
+}
+
+impl Recording {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Recording> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer(io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// A fresh intersection set up like the recorded one.
+    fn intersection(&self) -> io::Result<Intersection> {
+        self.layout.validate()?;
+        let mut intersection = Intersection::with_layout(self.seed, self.layout);
+        intersection.safe_distance = self.safe_distance;
+        intersection.deadlock_ticks = self.deadlock_ticks;
+        intersection.vehicle_mix = self.vehicle_mix;
+        match controller::by_name(&self.controller, &self.layout) {
+            Some(controller) => intersection.set_controller(controller),
+            None => {
+                let msg = format!("unknown controller {}", self.controller);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+        }
+        Ok(intersection)
+    }
+}
+
+/// Plays a recording back. Going backwards reruns the recording from the start.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    recording: Recording,
+    intersection: Intersection,
+    next: usize,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> io::Result<Replay> {
+        Ok(Replay { intersection: recording.intersection()?, recording, next: 0 })
+    }
+
+    pub fn intersection(&self) -> &Intersection {
+        &self.intersection
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.intersection.ticks()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.tick() >= self.recording.ticks
+    }
+
+    /// Simulates one tick, feeding in the inputs recorded for it.
+    pub fn step(&mut self) {
+        let inputs = &self.recording.inputs;
+        while self.next < inputs.len() && inputs[self.next].tick() <= self.tick() {
+            match &inputs[self.next] {
+                Input::AddCar { routes, .. } => self.intersection.add_car(routes.clone()),
+                Input::Spawn { route, vehicle: None, .. } => self.intersection.spawn(*route),
+                Input::Spawn { route, vehicle: Some(kind), .. } => self.intersection.spawn_kind(*route, *kind),
+            };
+            self.next += 1;
+        }
+        self.intersection.step(TIME_STEP);
+    }
+
+    /// Jumps to `tick`, clamped to the recorded length.
+    pub fn seek(&mut self, tick: u64) {
+        let tick = tick.min(self.recording.ticks);
+        if tick < self.tick() {
+            self.intersection = self.recording.intersection().expect("controller was valid when replay started");
+            self.next = 0;
+        }
+        while self.tick() < tick {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_same;
+
+    const TICKS: u64 = 2400;
+
+    #[test]
+    fn replay_matches_the_recorded_run() {
+        let mut recorded = Intersection::with_seed(3);
+        recorded.set_controller(controller::by_name("reservation", recorded.layout()).unwrap());
+        let mut halfway = None;
+        for tick in 0..TICKS {
+            if tick == TICKS / 2 {
+                halfway = Some(recorded.clone());
+            }
+            // both kinds of input: random routes and fixed ones
+            match tick % 60 {
+                0 => { recorded.add_car(Route::ALL.to_vec()); }
+                30 => { recorded.spawn(Route::ALL[(tick / 60) as usize % 12]); }
+                _ => {}
+            }
+            recorded.step(TIME_STEP);
+        }
+        assert!(recorded.number_of_passed_vehicles > 0);
+
+        let saved = serde_json::to_string(&recorded.recording()).unwrap();
+        let mut replay = Replay::new(serde_json::from_str(&saved).unwrap()).unwrap();
+        while !replay.finished() {
+            replay.step();
+        }
+        assert_same(replay.intersection(), &recorded);
+
+        replay.seek(TICKS / 2);
+        assert_same(replay.intersection(), &halfway.unwrap());
+        replay.seek(TICKS);
+        assert_same(replay.intersection(), &recorded);
+    }
+}
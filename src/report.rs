@@ -0,0 +1,230 @@
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::collision::{CloseCall, CollisionEvent};
+use crate::vehicle::VehicleKind;
+use crate::{CarTiming, Intersection, Route};
+
+const CAR_COLUMNS: &[&str] = &[
+    "id", "route", "kind", "spawned_at", "entered_at", "exited_at", "left_at", "travel_time", "crossing_time",
+];
+const COLLISION_COLUMNS: &[&str] = &["car_a", "car_b", "x", "y", "time"];
+const CLOSE_CALL_COLUMNS: &[&str] = &["car_a", "car_b", "gap", "time"];
+
+/// One row per car that drove away.
+#[derive(Debug, Clone, Serialize)]
+pub struct CarRecord {
+    pub id: u32,
+    pub route: Route,
+    pub kind: VehicleKind,
+    pub spawned_at: f32,
+    pub entered_at: Option<f32>,
+    pub exited_at: Option<f32>,
+    pub left_at: f32,
+    pub travel_time: f32,
+    pub crossing_time: Option<f32>,
+}
+
+impl From<&CarTiming> for CarRecord {
+    fn from(timing: &CarTiming) -> Self {
+        CarRecord {
+            id: timing.id,
+            route: timing.route,
+            kind: timing.kind,
+            spawned_at: timing.spawned_at,
+            entered_at: timing.entered_at,
+            exited_at: timing.exited_at,
+            left_at: timing.left_at,
+            travel_time: timing.travel_time(),
+            crossing_time: timing.crossing_time(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CollisionRecord {
+    pub car_a: u32,
+    pub car_b: u32,
+    pub x: f32,
+    pub y: f32,
+    pub time: f32,
+}
+
+impl From<&CollisionEvent> for CollisionRecord {
+    fn from(event: &CollisionEvent) -> Self {
+        CollisionRecord {
+            car_a: event.cars.0,
+            car_b: event.cars.1,
+            x: event.position.x,
+            y: event.position.y,
+            time: event.time,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CloseCallRecord {
+    pub car_a: u32,
+    pub car_b: u32,
+    pub gap: f32,
+    pub time: f32,
+}
+
+impl From<&CloseCall> for CloseCallRecord {
+    fn from(close_call: &CloseCall) -> Self {
+        CloseCallRecord {
+            car_a: close_call.cars.0,
+            car_b: close_call.cars.1,
+            gap: close_call.gap,
+            time: close_call.time,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Aggregates {
+    pub seed: u64,
+    pub simulated_seconds: f32,
+    pub passed_vehicles: u32,
+    pub max_velocity: f32,
+    pub min_velocity: Option<f32>,
+    pub collisions: u32,
+    pub close_calls: u32,
+    pub deadlocks: u32,
+    pub travel_time_min: Option<f32>,
+    pub travel_time_max: Option<f32>,
+    pub travel_time_mean: Option<f32>,
+    pub travel_time_p50: Option<f32>,
+    pub travel_time_p95: Option<f32>,
+    pub crossing_time_min: Option<f32>,
+    pub crossing_time_max: Option<f32>,
+    pub crossing_time_mean: Option<f32>,
+    pub crossing_time_p50: Option<f32>,
+    pub crossing_time_p95: Option<f32>,
+}
+
+/// Statistics of a run: aggregates plus per-car and per-incident records.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub aggregates: Aggregates,
+    pub cars: Vec<CarRecord>,
+    pub collisions: Vec<CollisionRecord>,
+    pub close_calls: Vec<CloseCallRecord>,
+}
+
+impl Report {
+    pub fn new(intersection: &Intersection) -> Report {
+        let travel = intersection.travel_time_stats();
+        let times = intersection.crossing_time_stats();
+        Report {
+            aggregates: Aggregates {
+                seed: intersection.seed(),
+                simulated_seconds: intersection.time(),
+                passed_vehicles: intersection.number_of_passed_vehicles,
+                max_velocity: intersection.max_velocity,
+                min_velocity: intersection.min_velocity,
+                collisions: intersection.collapse,
+                close_calls: intersection.close_calls,
+                deadlocks: intersection.deadlocks().len() as u32,
+                travel_time_min: travel.map(|t| t.min),
+                travel_time_max: travel.map(|t| t.max),
+                travel_time_mean: travel.map(|t| t.mean),
+                travel_time_p50: intersection.travel_time_percentile(50.0),
+                travel_time_p95: intersection.travel_time_percentile(95.0),
+                crossing_time_min: times.map(|t| t.min),
+                crossing_time_max: times.map(|t| t.max),
+                crossing_time_mean: times.map(|t| t.mean),
+                crossing_time_p50: intersection.crossing_time_percentile(50.0),
+                crossing_time_p95: intersection.crossing_time_percentile(95.0),
+            },
+            cars: intersection.finished_cars().iter().map(CarRecord::from).collect(),
+            collisions: intersection.collisions().iter().map(CollisionRecord::from).collect(),
+            close_calls: intersection.close_call_events().iter().map(CloseCallRecord::from).collect(),
+        }
+    }
+
+    /// Writes the whole report, aggregates, cars and incidents, as pretty-printed JSON.
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// Writes the report as CSV: one row per car to `path`, and the aggregates, collisions
+    /// and close calls to files next to it, e.g. `statistics_summary.csv` for `statistics.csv`.
+    /// Every file has its header, even without any rows.
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        write_rows(path, CAR_COLUMNS, &self.cars)?;
+        let mut writer = csv::Writer::from_path(sibling(path, "summary"))?;
+        writer.serialize(&self.aggregates)?;
+        writer.flush()?;
+        write_rows(sibling(path, "collisions"), COLLISION_COLUMNS, &self.collisions)?;
+        write_rows(sibling(path, "close_calls"), CLOSE_CALL_COLUMNS, &self.close_calls)
+    }
+}
+
+fn write_rows<T: Serialize, P: AsRef<Path>>(path: P, columns: &[&str], rows: &[T]) -> io::Result<()> {
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_path(path)?;
+    writer.write_record(columns)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()
+}
+
+/// `path` with `_<suffix>` added to its file name, keeping the extension.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{}_{}.{}", stem, suffix, extension.to_string_lossy())),
+        None => path.with_file_name(format!("{}_{}", stem, suffix)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{Route, TIME_STEP};
+
+    fn first_line(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap().lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn csv_files_have_headers() {
+        let dir = std::env::temp_dir().join(format!("smart_road_report_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("statistics.csv");
+
+        // an empty run writes just the headers
+        Report::new(&Intersection::with_seed(1)).write_csv(&path).unwrap();
+        assert_eq!(first_line(path.clone()), CAR_COLUMNS.join(","));
+        assert_eq!(first_line(dir.join("statistics_collisions.csv")), COLLISION_COLUMNS.join(","));
+        assert_eq!(first_line(dir.join("statistics_close_calls.csv")), CLOSE_CALL_COLUMNS.join(","));
+        let summary = fs::read_to_string(dir.join("statistics_summary.csv")).unwrap();
+        assert_eq!(summary.lines().count(), 2);
+        assert!(summary.starts_with("seed,simulated_seconds,passed_vehicles,"));
+
+        // and the columns are the ones the records are written with
+        let mut intersection = Intersection::with_seed(1);
+        intersection.spawn(Route::N_S);
+        while intersection.finished_cars().is_empty() {
+            intersection.step(TIME_STEP);
+        }
+        let report = Report::new(&intersection);
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&report.cars[0]).unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(written.lines().next().unwrap(), CAR_COLUMNS.join(","));
+        report.write_csv(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
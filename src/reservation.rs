@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::controller::{follow, Context, Controller, Decision};
+use crate::layout::IntersectionLayout;
+use crate::snapshot::pairs;
+use crate::{Car, CAR_SPEED_FAST, CAR_SPEED_NORMAL, CAR_SPEED_SLOW};
+
+/// Tiles per lane width along each axis of the crossing box.
+const TILES_PER_LANE: u32 = 2;
+/// Length of one reservation time slot in seconds.
+const SLOT_LENGTH: f32 = 0.05;
+/// Neighbouring slots that must also be free, to absorb small timing errors.
+const SLOT_SLACK: u64 = 1;
+/// Extra margin around a car when computing which tiles it covers.
+const TILE_MARGIN: f32 = 3.0;
+/// Give up simulating a trajectory that takes longer than this.
+const MAX_TRAJECTORY_TIME: f32 = 30.0;
+/// A car asks again every tick until it gets through; its failed requests within this
+/// many seconds count as one rejection.
+const REJECTION_WINDOW: f32 = 1.0;
+
+/// Autonomous intersection management: the crossing box is split into a grid of
+/// tiles and every car asks for the tiles its path covers, slot by slot, before
+/// it may enter. A request is granted only if none of those tiles is held by
+/// another car at the same time; the car then drives at the granted speed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReservationManager {
+    tile_size: f32,
+    tiles: u32,
+    #[serde(with = "pairs")]
+    reserved: HashMap<(u32, u32, u64), u32>,
+    granted: HashMap<u32, f32>,
+    // Start of each waiting car's current rejection window.
+    #[serde(default)]
+    rejected_since: HashMap<u32, f32>,
+    pub granted_requests: u32,
+    /// Failed requests, at most one per car per `REJECTION_WINDOW`.
+    pub rejected_requests: u32,
+}
+
+impl ReservationManager {
+    pub fn new(layout: &IntersectionLayout) -> ReservationManager {
+        ReservationManager {
+            tile_size: layout.lane_width / TILES_PER_LANE as f32,
+            tiles: 2 * layout.lanes_per_approach * TILES_PER_LANE,
+            reserved: HashMap::new(),
+            granted: HashMap::new(),
+            rejected_since: HashMap::new(),
+            granted_requests: 0,
+            rejected_requests: 0,
+        }
+    }
+
+    /// Speed the car was granted, if it holds a reservation.
+    pub fn granted_speed(&self, car_id: u32) -> Option<f32> {
+        self.granted.get(&car_id).copied()
+    }
+
+    /// Tries the candidate speeds from fastest to slowest, never faster than `max_speed`,
+    /// and reserves the first trajectory whose tiles are all free. Returns the granted speed.
+    pub fn request(&mut self, car: &Car, max_speed: f32, layout: &IntersectionLayout, now: f32) -> Option<f32> {
+        self.expire(now);
+        for speed in [CAR_SPEED_FAST, CAR_SPEED_NORMAL, CAR_SPEED_SLOW] {
+            if speed > max_speed {
+                continue;
+            }
+            if let Some(cells) = self.free_trajectory(car, speed, layout, now) {
+                for cell in cells {
+                    self.reserved.insert(cell, car.id);
+                }
+                self.granted.insert(car.id, speed);
+                self.rejected_since.remove(&car.id);
+                self.granted_requests += 1;
+                return Some(speed);
+            }
+        }
+        if self.rejected_since.get(&car.id).is_none_or(|since| now - since >= REJECTION_WINDOW) {
+            self.rejected_since.insert(car.id, now);
+            self.rejected_requests += 1;
+        }
+        None
+    }
+
+    /// Drops everything held by a car that has left the simulation.
+    pub fn forget(&mut self, car_id: u32) {
+        self.granted.remove(&car_id);
+        self.rejected_since.remove(&car_id);
+        self.reserved.retain(|_, id| *id != car_id);
+    }
+
+    fn expire(&mut self, now: f32) {
+        let current = slot(now);
+        self.reserved.retain(|(_, _, s), _| s + SLOT_SLACK >= current);
+    }
+
+    /// Space-time cells a car would occupy driving at `speed` from now on,
+    /// or `None` if any of them (or a neighbouring slot) is taken.
+    fn free_trajectory(&self, car: &Car, speed: f32, layout: &IntersectionLayout, now: f32) -> Option<Vec<(u32, u32, u64)>> {
+        let mut ghost = *car;
+        ghost.target_speed = speed;
+        let mut cells = Vec::new();
+        let mut t = 0.0;
+        while !ghost.after_cross_road() {
+            if t > MAX_TRAJECTORY_TIME {
+                return None;
+            }
+            let s = slot(now + t);
+            for (x, y) in self.covered_tiles(&ghost, layout) {
+                for neighbour in s.saturating_sub(SLOT_SLACK)..=s + SLOT_SLACK {
+                    if self.reserved.get(&(x, y, neighbour)).is_some_and(|id| *id != car.id) {
+                        return None;
+                    }
+                }
+                cells.push((x, y, s));
+            }
+            ghost.drive(SLOT_LENGTH);
+            t += SLOT_LENGTH;
+        }
+        Some(cells)
+    }
+
+    fn covered_tiles(&self, car: &Car, layout: &IntersectionLayout) -> Vec<(u32, u32)> {
+        let to_tile = |v: f32, origin: f32| ((v - origin) / self.tile_size).floor();
+        let last = self.tiles as f32 - 1.0;
+        let (min, max) = car.bounds();
+        let x0 = to_tile(min.x - TILE_MARGIN, layout.box_x);
+        let x1 = to_tile(max.x + TILE_MARGIN, layout.box_x);
+        let y0 = to_tile(min.y - TILE_MARGIN, layout.box_y);
+        let y1 = to_tile(max.y + TILE_MARGIN, layout.box_y);
+        if x1 < 0.0 || y1 < 0.0 || x0 > last || y0 > last {
+            return vec![];
+        }
+        let mut tiles = vec![];
+        for x in x0.max(0.0) as u32..=x1.min(last) as u32 {
+            for y in y0.max(0.0) as u32..=y1.min(last) as u32 {
+                tiles.push((x, y));
+            }
+        }
+        tiles
+    }
+}
+
+impl Controller for ReservationManager {
+    fn name(&self) -> &'static str {
+        "reservation"
+    }
+
+    fn decide(&mut self, car: &Car, context: &Context) -> Decision {
+        if let Some(speed) = self.granted_speed(car.id) {
+            return Decision { target_speed: speed, cleared: true };
+        }
+        if car.before_cross_road(context.layout) {
+            return Decision { target_speed: follow(context.leader), cleared: false };
+        }
+        // only the first car without a reservation asks, and never faster than its leader
+        let max_speed = match context.leader {
+            Some(leader) => self.granted_speed(leader.id),
+            None => Some(CAR_SPEED_FAST),
+        };
+        let max_speed = max_speed.map(|max| max.min(car.kind.max_speed()));
+        match max_speed.and_then(|max| self.request(car, max, context.layout, context.time)) {
+            Some(speed) => Decision { target_speed: speed, cleared: true },
+            None => Decision { target_speed: CAR_SPEED_SLOW, cleared: false },
+        }
+    }
+
+    fn car_left(&mut self, car_id: u32) {
+        self.forget(car_id);
+    }
+
+    fn state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("reservation state is serializable")
+    }
+
+    fn restore(&mut self, state: serde_json::Value) -> io::Result<()> {
+        *self = serde_json::from_value(state)?;
+        Ok(())
+    }
+
+    fn summary(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("reservations_granted", self.granted_requests.to_string()),
+            ("reservations_rejected", self.rejected_requests.to_string()),
+        ]
+    }
+
+    fn box_clone(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+fn slot(time: f32) -> u64 {
+    (time / SLOT_LENGTH) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vehicle::VehicleKind;
+    use crate::Route;
+
+    // A car on `route` with its front just inside the crossing box.
+    fn car_at_box(id: u32, route: Route, layout: &IntersectionLayout) -> Car {
+        let mut car = Car::new(layout.path(route), VehicleKind::Car, 0, CAR_SPEED_SLOW, id, route, 0.0);
+        car.distance = car.path.box_entry() - car.rectangle.0 / 2.0 + 1.0;
+        car.drive(0.0);
+        car
+    }
+
+    #[test]
+    fn grants_a_free_box() {
+        let layout = IntersectionLayout::default();
+        let mut manager = ReservationManager::new(&layout);
+        let car = car_at_box(1, Route::N_S, &layout);
+        assert_eq!(manager.request(&car, CAR_SPEED_FAST, &layout, 0.0), Some(CAR_SPEED_FAST));
+        assert_eq!(manager.granted_speed(1), Some(CAR_SPEED_FAST));
+        assert!(manager.reserved.values().all(|id| *id == 1));
+    }
+
+    #[test]
+    fn never_faster_than_asked() {
+        let layout = IntersectionLayout::default();
+        let mut manager = ReservationManager::new(&layout);
+        let car = car_at_box(1, Route::N_S, &layout);
+        assert_eq!(manager.request(&car, CAR_SPEED_NORMAL, &layout, 0.0), Some(CAR_SPEED_NORMAL));
+    }
+
+    #[test]
+    fn grants_routes_that_do_not_conflict() {
+        let layout = IntersectionLayout::default();
+        let mut manager = ReservationManager::new(&layout);
+        assert!(!Route::N_S.conflicts_with(Route::S_N));
+        let (a, b) = (car_at_box(1, Route::N_S, &layout), car_at_box(2, Route::S_N, &layout));
+        assert_eq!(manager.request(&a, CAR_SPEED_FAST, &layout, 0.0), Some(CAR_SPEED_FAST));
+        assert_eq!(manager.request(&b, CAR_SPEED_FAST, &layout, 0.0), Some(CAR_SPEED_FAST));
+    }
+
+    #[test]
+    fn rejects_a_trajectory_through_held_tiles() {
+        let layout = IntersectionLayout::default();
+        let mut manager = ReservationManager::new(&layout);
+        let a = car_at_box(1, Route::N_S, &layout);
+        assert_eq!(manager.request(&a, CAR_SPEED_FAST, &layout, 0.0), Some(CAR_SPEED_FAST));
+        // a car where the first one is now can only go once it has gone
+        let b = car_at_box(2, Route::N_S, &layout);
+        assert_eq!(manager.request(&b, CAR_SPEED_FAST, &layout, 0.0), None);
+        assert_eq!(manager.granted_speed(2), None);
+        assert_eq!((manager.granted_requests, manager.rejected_requests), (1, 1));
+    }
+
+    #[test]
+    fn conflicting_trajectories_never_share_a_slot() {
+        let layout = IntersectionLayout::default();
+        let mut manager = ReservationManager::new(&layout);
+        let a = car_at_box(1, Route::N_S, &layout);
+        assert_eq!(manager.request(&a, CAR_SPEED_FAST, &layout, 0.0), Some(CAR_SPEED_FAST));
+        let held = manager.reserved.clone();
+        let b = car_at_box(2, Route::W_E, &layout);
+        assert!(b.route.conflicts_with(a.route));
+        // arriving while the first car crosses, it only fits in behind it at the slowest speed
+        assert_eq!(manager.request(&b, CAR_SPEED_FAST, &layout, 0.4), Some(CAR_SPEED_SLOW));
+        for (x, y, s) in held.keys() {
+            for neighbour in s.saturating_sub(SLOT_SLACK)..=s + SLOT_SLACK {
+                assert_ne!(manager.reserved.get(&(*x, *y, neighbour)), Some(&2));
+            }
+        }
+    }
+
+    #[test]
+    fn slots_expire() {
+        let layout = IntersectionLayout::default();
+        let mut manager = ReservationManager::new(&layout);
+        let a = car_at_box(1, Route::N_S, &layout);
+        manager.request(&a, CAR_SPEED_FAST, &layout, 0.0);
+        let last = *manager.reserved.keys().map(|(_, _, s)| s).max().unwrap();
+        let end = (last + SLOT_SLACK + 1) as f32 * SLOT_LENGTH;
+
+        manager.expire(end - SLOT_LENGTH);
+        assert!(!manager.reserved.is_empty());
+        manager.expire(end + SLOT_LENGTH / 2.0);
+        assert!(manager.reserved.is_empty());
+
+        // once the slots are gone the same place can be reserved again
+        let b = car_at_box(2, Route::N_S, &layout);
+        assert_eq!(manager.request(&b, CAR_SPEED_FAST, &layout, end), Some(CAR_SPEED_FAST));
+    }
+
+    #[test]
+    fn retries_count_once_per_window() {
+        let layout = IntersectionLayout::default();
+        let mut manager = ReservationManager::new(&layout);
+        let a = car_at_box(1, Route::N_S, &layout);
+        let b = car_at_box(2, Route::N_S, &layout);
+        manager.request(&a, CAR_SPEED_FAST, &layout, 0.0);
+        // the first car's slots are kept held as if it never moved
+        let held = manager.reserved.clone();
+        for tick in 0..150 {
+            manager.reserved.extend(held.iter().map(|((x, y, _), id)| ((*x, *y, slot(tick as f32 / 60.0)), *id)));
+            assert_eq!(manager.request(&b, CAR_SPEED_FAST, &layout, tick as f32 / 60.0), None);
+        }
+        assert_eq!(manager.rejected_requests, 3);
+        manager.forget(1);
+        assert!(manager.request(&b, CAR_SPEED_FAST, &layout, 2.5).is_some());
+        assert_eq!(manager.rejected_requests, 3);
+    }
+}
@@ -0,0 +1,139 @@
+use std::io;
+
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::layout::read_config;
+use crate::network::RoadNetwork;
+use crate::traffic::{arrival_rng, interarrival};
+use crate::Direction;
+
+/// A Poisson stream of `rate` vehicles per second entering the network at intersection
+/// `origin` moving in `entering`, and leaving it at `destination` moving in `leaving`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OdFlow {
+    pub origin: usize,
+    pub entering: Direction,
+    pub destination: usize,
+    pub leaving: Direction,
+    pub rate: f32,
+}
+
+/// Origin–destination demand for a road network.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OdDemand {
+    pub flows: Vec<OdFlow>,
+    /// Replan trips around queues on the way, see `RoadNetwork::reroute`.
+    pub reroute: bool,
+}
+
+impl OdDemand {
+    /// Loads flows with no negative rate; `check` matches them against a network.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> io::Result<OdDemand> {
+        let demand: OdDemand = read_config(path)?;
+        if demand.flows.iter().any(|f| f.rate < 0.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "rates must not be negative"));
+        }
+        Ok(demand)
+    }
+
+    /// Checks that every flow enters and leaves `network` at one of its edges.
+    pub fn check(&self, network: &RoadNetwork) -> io::Result<()> {
+        let (entries, exits) = (network.entries(), network.exits());
+        for flow in &self.flows {
+            let problem = if !entries.contains(&(flow.origin, flow.entering)) {
+                Some(format!("no entry into intersection {} going {:?}", flow.origin, flow.entering))
+            } else if !exits.contains(&(flow.destination, flow.leaving)) {
+                Some(format!("no exit from intersection {} going {:?}", flow.destination, flow.leaving))
+            } else {
+                None
+            };
+            if let Some(msg) = problem {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// `rate` vehicles per second from every entry of `network`, spread evenly over
+    /// all exits other than straight back out where they came from.
+    pub fn uniform(network: &RoadNetwork, rate: f32) -> OdDemand {
+        let exits = network.exits();
+        let mut flows = vec![];
+        for (origin, entering) in network.entries() {
+            let destinations: Vec<_> = exits.iter()
+                .filter(|(i, d)| (*i, *d) != (origin, opposite(entering)))
+                .collect();
+            for &&(destination, leaving) in &destinations {
+                let rate = rate / destinations.len() as f32;
+                flows.push(OdFlow { origin, entering, destination, leaving, rate });
+            }
+        }
+        OdDemand { flows, reroute: false }
+    }
+}
+
+fn opposite(direction: Direction) -> Direction {
+    match direction {
+        Direction::Left => Direction::Right,
+        Direction::Right => Direction::Left,
+        Direction::Down => Direction::Up,
+        Direction::Up => Direction::Down,
+    }
+}
+
+/// Starts trips on a road network according to an `OdDemand`, each planned along the
+/// fastest way to its destination.
+#[derive(Debug, Clone)]
+pub struct OdGenerator {
+    demand: OdDemand,
+    rng: ChaCha12Rng,
+    // Network time of the next arrival of each flow.
+    next_arrival: Vec<Option<f32>>,
+    pub generated: u32,
+    /// Arrivals dropped because their destination cannot be reached from their origin.
+    pub unroutable: u32,
+}
+
+impl OdGenerator {
+    /// Fails if a flow does not fit `network`, see `OdDemand::check`.
+    pub fn new(demand: OdDemand, seed: u64, network: &RoadNetwork) -> io::Result<OdGenerator> {
+        demand.check(network)?;
+        Ok(OdGenerator {
+            next_arrival: vec![None; demand.flows.len()],
+            demand,
+            rng: arrival_rng(seed),
+            generated: 0,
+            unroutable: 0,
+        })
+    }
+
+    pub fn demand(&self) -> &OdDemand {
+        &self.demand
+    }
+
+    /// Generates the arrivals up to the network's current time.
+    pub fn update(&mut self, network: &mut RoadNetwork) {
+        network.reroute = self.demand.reroute;
+        let now = network.time();
+        for (i, flow) in self.demand.flows.iter().enumerate() {
+            if flow.rate <= 0.0 {
+                self.next_arrival[i] = None;
+                continue;
+            }
+            let mut next = match self.next_arrival[i] {
+                Some(next) => next,
+                None => now + interarrival(&mut self.rng, flow.rate),
+            };
+            while next <= now {
+                match network.plan_trip(flow.origin, flow.entering, (flow.destination, flow.leaving)) {
+                    Ok(_) => self.generated += 1,
+                    Err(_) => self.unroutable += 1,
+                }
+                next += interarrival(&mut self.rng, flow.rate);
+            }
+            self.next_arrival[i] = Some(next);
+        }
+    }
+}
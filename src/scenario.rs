@@ -0,0 +1,123 @@
+use std::io;
+
+use serde::Deserialize;
+
+use crate::layout::read_config;
+use crate::{Intersection, Route};
+
+/// A car to spawn on `route` once the simulation reaches `time` seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Spawn {
+    pub time: f32,
+    pub route: Route,
+}
+
+/// Outcome a scenario has to reach by the end of the run; unset fields are not checked.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Expectations {
+    pub passed_vehicles: Option<u32>,
+    pub max_collisions: Option<u32>,
+    pub max_close_calls: Option<u32>,
+    pub max_deadlocks: Option<u32>,
+    /// Longest time any car may take from spawning to driving away, in seconds.
+    pub max_travel_time: Option<f32>,
+    /// Longest time any car may spend at the intersection, see `CarTiming::crossing_time`.
+    pub max_crossing_time: Option<f32>,
+}
+
+/// Scripted traffic: explicit spawns at fixed times instead of random routes.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    pub seed: Option<u64>,
+    pub controller: Option<String>,
+    /// Seconds to simulate; by default until every car has driven away.
+    pub duration: Option<f32>,
+    pub spawns: Vec<Spawn>,
+    pub expect: Expectations,
+}
+
+impl Scenario {
+    /// Loads a scenario and sorts its spawns by time; nothing else is checked.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> io::Result<Scenario> {
+        let mut scenario: Scenario = read_config(path)?;
+        scenario.spawns.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(scenario)
+    }
+
+    /// Every expectation the intersection does not meet, described for the user.
+    pub fn check(&self, intersection: &Intersection) -> Vec<String> {
+        let expect = &self.expect;
+        let mut failures = vec![];
+        if let Some(passed) = expect.passed_vehicles {
+            if intersection.number_of_passed_vehicles != passed {
+                failures.push(format!("expected {} passed vehicles, got {}", passed, intersection.number_of_passed_vehicles));
+            }
+        }
+        if let Some(max) = expect.max_collisions {
+            if intersection.collapse > max {
+                failures.push(format!("expected at most {} collisions, got {}", max, intersection.collapse));
+            }
+        }
+        if let Some(max) = expect.max_close_calls {
+            if intersection.close_calls > max {
+                failures.push(format!("expected at most {} close calls, got {}", max, intersection.close_calls));
+            }
+        }
+        if let Some(max) = expect.max_deadlocks {
+            let deadlocks = intersection.deadlocks().len() as u32;
+            if deadlocks > max {
+                failures.push(format!("expected at most {} deadlocks, got {}", max, deadlocks));
+            }
+        }
+        if let (Some(max), Some(times)) = (expect.max_travel_time, intersection.travel_time_stats()) {
+            if times.max > max {
+                failures.push(format!("expected travel times of at most {:.2}s, got {:.2}s", max, times.max));
+            }
+        }
+        if let (Some(max), Some(times)) = (expect.max_crossing_time, intersection.crossing_time_stats()) {
+            if times.max > max {
+                failures.push(format!("expected crossing times of at most {:.2}s, got {:.2}s", max, times.max));
+            }
+        }
+        failures
+    }
+}
+
+/// Plays a scenario into an intersection. A spawn whose lane is still full at the
+/// canvas edge is retried every tick until the car fits.
+#[derive(Debug, Clone)]
+pub struct ScenarioPlayer {
+    scenario: Scenario,
+    next: usize,
+    pending: Vec<Route>,
+}
+
+impl ScenarioPlayer {
+    pub fn new(scenario: Scenario) -> ScenarioPlayer {
+        ScenarioPlayer { scenario, next: 0, pending: Vec::new() }
+    }
+
+    pub fn scenario(&self) -> &Scenario {
+        &self.scenario
+    }
+
+    /// Spawns everything that is due at the intersection's current time.
+    pub fn update(&mut self, intersection: &mut Intersection) {
+        let spawns = &self.scenario.spawns;
+        while self.next < spawns.len() && spawns[self.next].time <= intersection.time() {
+            self.pending.push(spawns[self.next].route);
+            self.next += 1;
+        }
+        self.pending.retain(|route| intersection.spawn(*route).is_none());
+    }
+
+    /// Whether the run is over: past `duration`, or without one, once all cars are spawned and gone.
+    pub fn finished(&self, intersection: &Intersection) -> bool {
+        match self.scenario.duration {
+            Some(duration) => intersection.time() >= duration,
+            None => self.next == self.scenario.spawns.len() && self.pending.is_empty() && intersection.cars().next().is_none(),
+        }
+    }
+}
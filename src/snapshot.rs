@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::controller;
+use crate::Intersection;
+
+/// Full simulation state at one tick. The controller is stored by name plus whatever
+/// state it exports, and rebuilt on load.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub controller: String,
+    pub controller_state: serde_json::Value,
+    pub intersection: Intersection,
+}
+
+impl Snapshot {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer(io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// The intersection as it was, with its controller restored.
+    pub fn restore(self) -> io::Result<Intersection> {
+        let mut intersection = self.intersection;
+        intersection.layout().validate()?;
+        let mut controller = controller::by_name(&self.controller, intersection.layout()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("unknown controller {}", self.controller))
+        })?;
+        controller.restore(self.controller_state)?;
+        intersection.set_controller(controller);
+        intersection.rebuild_grid();
+        Ok(intersection)
+    }
+}
+
+/// Serializes a `Vec2` as an `(x, y)` pair, for `#[serde(with = "...")]`.
+pub(crate) mod vec2 {
+    use macroquad::math::Vec2;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &Vec2, serializer: S) -> Result<S::Ok, S::Error> {
+        (v.x, v.y).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec2, D::Error> {
+        let (x, y) = <(f32, f32)>::deserialize(deserializer)?;
+        Ok(Vec2::new(x, y))
+    }
+}
+
+/// Serializes a map as a list of `(key, value)` pairs, for keys JSON cannot use as object keys.
+pub(crate) mod pairs {
+    use std::collections::HashMap;
+    use std::hash::Hash;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where K: Serialize, V: Serialize, S: Serializer {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where K: Deserialize<'de> + Eq + Hash, V: Deserialize<'de>, D: Deserializer<'de> {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_same;
+    use crate::{Route, TIME_STEP};
+
+    const MINUTE: u64 = 3600;
+
+    fn start(controller: &str) -> Intersection {
+        let mut intersection = Intersection::with_seed(7);
+        intersection.set_controller(controller::by_name(controller, intersection.layout()).unwrap());
+        intersection
+    }
+
+    // a car on a random route every half second
+    fn run(intersection: &mut Intersection, ticks: u64) {
+        for _ in 0..ticks {
+            if intersection.ticks().is_multiple_of(30) {
+                intersection.add_car(Route::ALL.to_vec());
+            }
+            intersection.step(TIME_STEP);
+        }
+    }
+
+    fn continues_like_a_straight_run(controller: &str) {
+        let mut straight = start(controller);
+        run(&mut straight, 2 * MINUTE);
+
+        let mut first = start(controller);
+        run(&mut first, MINUTE);
+        let saved = serde_json::to_string(&first.snapshot()).unwrap();
+        let loaded: Snapshot = serde_json::from_str(&saved).unwrap();
+        let mut resumed = loaded.restore().unwrap();
+        run(&mut resumed, MINUTE);
+
+        assert!(straight.number_of_passed_vehicles > 0);
+        assert_same(&straight, &resumed);
+    }
+
+    #[test]
+    fn queue_continues_like_a_straight_run() {
+        continues_like_a_straight_run("queue");
+    }
+
+    #[test]
+    fn reservation_continues_like_a_straight_run() {
+        continues_like_a_straight_run("reservation");
+    }
+
+    #[test]
+    fn traffic_light_continues_like_a_straight_run() {
+        continues_like_a_straight_run("traffic_light");
+    }
+
+    #[test]
+    fn actuated_light_continues_like_a_straight_run() {
+        continues_like_a_straight_run("actuated_light");
+    }
+}
@@ -0,0 +1,36 @@
+use crate::controller::{Context, Controller, Decision};
+use crate::report::Report;
+use crate::{Car, Intersection, CAR_SPEED_NORMAL};
+
+/// The intersection's cars, ordered by id.
+pub fn cars(intersection: &Intersection) -> Vec<Car> {
+    let mut cars: Vec<Car> = intersection.cars().copied().collect();
+    cars.sort_by_key(|car| car.id);
+    cars
+}
+
+/// Asserts that two runs got to the same tick with the same cars and the same statistics.
+pub fn assert_same(a: &Intersection, b: &Intersection) {
+    assert_eq!(a.ticks(), b.ticks());
+    assert_eq!(cars(a), cars(b));
+    let (a, b) = (Report::new(a), Report::new(b));
+    assert_eq!(serde_json::to_value(a).unwrap(), serde_json::to_value(b).unwrap());
+}
+
+/// Never lets a car into the crossing, so only the deadlock breaker gets anyone through.
+#[derive(Debug, Clone)]
+pub struct Hold;
+
+impl Controller for Hold {
+    fn name(&self) -> &'static str {
+        "hold"
+    }
+
+    fn decide(&mut self, _car: &Car, _context: &Context) -> Decision {
+        Decision { target_speed: CAR_SPEED_NORMAL, cleared: false }
+    }
+
+    fn box_clone(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,188 @@
+use std::io;
+
+use r::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::layout::read_config;
+use crate::{Direction, Intersection, Route, Turning};
+
+// Mixed into the seed of arrival streams, so they do not draw the same numbers as an
+// intersection seeded with the plain seed.
+const ARRIVAL_STREAM: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Random numbers for arrivals, independent of those of `Intersection::with_seed(seed)`.
+pub fn arrival_rng(seed: u64) -> ChaCha12Rng {
+    ChaCha12Rng::seed_from_u64(seed ^ ARRIVAL_STREAM)
+}
+
+/// Exponentially distributed time to the next arrival of a Poisson stream.
+pub(crate) fn interarrival<R: Rng>(rng: &mut R, rate: f32) -> f32 {
+    let u: f32 = rng.gen();
+    -(1.0 - u).ln() / rate
+}
+
+/// Arrivals on one approach: a Poisson stream of `rate` cars per second, each turning
+/// left, going straight or turning right with probability proportional to its share.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApproachDemand {
+    pub rate: f32,
+    pub left: f32,
+    pub straight: f32,
+    pub right: f32,
+}
+
+impl Default for ApproachDemand {
+    fn default() -> Self {
+        ApproachDemand { rate: 0.3, left: 0.25, straight: 0.5, right: 0.25 }
+    }
+}
+
+/// Demand on all four approaches, named after the side cars come from.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Demand {
+    pub north: ApproachDemand,
+    pub south: ApproachDemand,
+    pub west: ApproachDemand,
+    pub east: ApproachDemand,
+}
+
+impl Demand {
+    /// Loads demand with no negative rate or turn ratio.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> io::Result<Demand> {
+        let demand: Demand = read_config(path)?;
+        let negative = demand.approaches().iter()
+            .any(|(_, a)| a.rate < 0.0 || a.left < 0.0 || a.straight < 0.0 || a.right < 0.0);
+        if negative {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "rates and turn ratios must not be negative"));
+        }
+        Ok(demand)
+    }
+
+    /// The same turn ratios with every rate multiplied by `factor`.
+    pub fn scaled(&self, factor: f32) -> Demand {
+        let scale = |a: ApproachDemand| ApproachDemand { rate: a.rate * factor, ..a };
+        Demand {
+            north: scale(self.north),
+            south: scale(self.south),
+            west: scale(self.west),
+            east: scale(self.east),
+        }
+    }
+
+    /// Each approach with the direction its cars travel in.
+    fn approaches(&self) -> [(Direction, ApproachDemand); 4] {
+        [
+            (Direction::Down, self.north),
+            (Direction::Up, self.south),
+            (Direction::Right, self.west),
+            (Direction::Left, self.east),
+        ]
+    }
+}
+
+/// Spawns cars according to a `Demand`. Cars that cannot enter yet because their
+/// lane is backed up to the canvas edge wait and are spawned as soon as there is room.
+#[derive(Debug, Clone)]
+pub struct TrafficGenerator {
+    demand: Demand,
+    rng: ChaCha12Rng,
+    // Simulation time of the next arrival on each approach, in `Demand::approaches` order.
+    next_arrival: [Option<f32>; 4],
+    pending: Vec<Route>,
+    pub generated: u32,
+}
+
+impl TrafficGenerator {
+    pub fn new(demand: Demand, seed: u64) -> TrafficGenerator {
+        TrafficGenerator {
+            demand,
+            rng: arrival_rng(seed),
+            next_arrival: [None; 4],
+            pending: Vec::new(),
+            generated: 0,
+        }
+    }
+
+    pub fn demand(&self) -> &Demand {
+        &self.demand
+    }
+
+    /// Switches to new demand from now on; cars already waiting to enter are kept.
+    pub fn set_demand(&mut self, demand: Demand) {
+        self.demand = demand;
+        self.next_arrival = [None; 4];
+    }
+
+    /// Cars that have arrived but could not be spawned yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Generates the arrivals up to the intersection's current time and spawns what fits.
+    pub fn update(&mut self, intersection: &mut Intersection) {
+        let now = intersection.time();
+        for (i, (direction, approach)) in self.demand.approaches().into_iter().enumerate() {
+            if approach.rate <= 0.0 {
+                self.next_arrival[i] = None;
+                continue;
+            }
+            let mut next = match self.next_arrival[i] {
+                Some(next) => next,
+                None => now + interarrival(&mut self.rng, approach.rate),
+            };
+            while next <= now {
+                let route = self.pick_route(direction, &approach);
+                self.pending.push(route);
+                self.generated += 1;
+                next += interarrival(&mut self.rng, approach.rate);
+            }
+            self.next_arrival[i] = Some(next);
+        }
+        self.pending.retain(|route| intersection.spawn(*route).is_none());
+    }
+
+    fn pick_route(&mut self, direction: Direction, approach: &ApproachDemand) -> Route {
+        let total = approach.left + approach.straight + approach.right;
+        let turning = if total <= 0.0 {
+            Turning::None
+        } else {
+            let x = self.rng.gen_range(0.0..total);
+            if x < approach.left {
+                Turning::Left
+            } else if x < approach.left + approach.straight {
+                Turning::None
+            } else {
+                Turning::Right
+            }
+        };
+        *Route::ALL.iter()
+            .find(|r| r.get_direction() == direction && r.turning() == turning)
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TIME_STEP;
+
+    #[test]
+    fn arrivals_follow_the_rate() {
+        let demand = Demand::default();
+        let rate: f32 = demand.approaches().iter().map(|(_, a)| a.rate).sum();
+        let seconds = 400.0;
+        let mut intersection = Intersection::with_seed(1);
+        let mut generator = TrafficGenerator::new(demand, 1);
+        while intersection.time() < seconds {
+            generator.update(&mut intersection);
+            intersection.step(TIME_STEP);
+        }
+        let expected = rate * seconds;
+        // a Poisson count has a standard deviation of sqrt(expected), about 22 here
+        let generated = generator.generated as f32;
+        assert!((generated - expected).abs() < 0.15 * expected, "{} arrivals, expected {}", generated, expected);
+    }
+}
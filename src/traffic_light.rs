@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::controller::{follow, Context, Controller, Decision, Signal};
+use crate::{Car, Route, CAR_SPEED_FAST, CAR_SPEED_SLOW};
+
+const GREEN_TIME: f32 = 6.0;
+const YELLOW_TIME: f32 = 2.0;
+/// Actuated mode: a green lasts at least this long and at most `GREEN_TIME * 2`.
+const MIN_GREEN_TIME: f32 = 2.0;
+
+/// Classic signalised crossing, as a baseline for the smart controllers.
+///
+/// Phases are built from `Route::not_allowed_to_go`: routes are grouped greedily,
+/// most constrained first, so that no two routes in a phase conflict. Routes
+/// without conflicts (right turns) are always green. A fixed-cycle light gives
+/// every phase `GREEN_TIME`; an actuated one skips phases nobody waits for and
+/// ends a green early once its approaches are empty.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrafficLight {
+    phases: Vec<Vec<Route>>,
+    actuated: bool,
+    phase: usize,
+    yellow: bool,
+    phase_started: f32,
+    cleared: HashSet<u32>,
+}
+
+impl TrafficLight {
+    pub fn fixed() -> TrafficLight {
+        TrafficLight::new(false)
+    }
+
+    pub fn actuated() -> TrafficLight {
+        TrafficLight::new(true)
+    }
+
+    fn new(actuated: bool) -> TrafficLight {
+        TrafficLight {
+            phases: build_phases(),
+            actuated,
+            phase: 0,
+            yellow: false,
+            phase_started: 0.0,
+            cleared: HashSet::new(),
+        }
+    }
+
+    pub fn phases(&self) -> &[Vec<Route>] {
+        &self.phases
+    }
+
+    fn next_phase(&self, context: &Context) -> usize {
+        let next = (self.phase + 1) % self.phases.len();
+        if !self.actuated {
+            return next;
+        }
+        (0..self.phases.len())
+            .map(|i| (next + i) % self.phases.len())
+            .find(|p| self.waiting(*p, context))
+            .unwrap_or(next)
+    }
+
+    /// Whether a car of the phase is in the approach zone without having been let through.
+    fn waiting(&self, phase: usize, context: &Context) -> bool {
+        context.cars.values().any(|c| {
+            self.phases[phase].contains(&c.route) && !self.cleared.contains(&c.id)
+                && !c.before_cross_road(context.layout) && !c.after_cross_road()
+        })
+    }
+}
+
+impl Controller for TrafficLight {
+    fn name(&self) -> &'static str {
+        if self.actuated { "actuated_light" } else { "traffic_light" }
+    }
+
+    fn decide(&mut self, car: &Car, context: &Context) -> Decision {
+        if self.cleared.contains(&car.id) {
+            return Decision { target_speed: CAR_SPEED_FAST, cleared: true };
+        }
+        if car.before_cross_road(context.layout) {
+            return Decision { target_speed: follow(context.leader), cleared: false };
+        }
+        let leader_cleared = context.leader.is_none_or(|l| self.cleared.contains(&l.id));
+        if leader_cleared && self.signal(car.route) == Some(Signal::Green) {
+            self.cleared.insert(car.id);
+            return Decision { target_speed: CAR_SPEED_FAST, cleared: true };
+        }
+        Decision { target_speed: CAR_SPEED_SLOW, cleared: false }
+    }
+
+    /// Moves the light on, whether or not there are cars around.
+    fn tick(&mut self, context: &Context) {
+        let elapsed = context.time - self.phase_started;
+        if self.yellow {
+            if elapsed >= YELLOW_TIME {
+                self.phase = self.next_phase(context);
+                self.yellow = false;
+                self.phase_started = context.time;
+            }
+            return;
+        }
+        let end_green = if self.actuated {
+            let waiting_here = self.waiting(self.phase, context);
+            let waiting_elsewhere = (0..self.phases.len()).any(|p| p != self.phase && self.waiting(p, context));
+            elapsed >= GREEN_TIME * 2.0 || (elapsed >= MIN_GREEN_TIME && !waiting_here && waiting_elsewhere)
+        } else {
+            elapsed >= GREEN_TIME
+        };
+        if end_green {
+            self.yellow = true;
+            self.phase_started = context.time;
+        }
+    }
+
+    fn car_left(&mut self, car_id: u32) {
+        self.cleared.remove(&car_id);
+    }
+
+    fn force_grant(&mut self, car_id: u32) {
+        self.cleared.insert(car_id);
+    }
+
+    fn state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("traffic light state is serializable")
+    }
+
+    fn restore(&mut self, state: serde_json::Value) -> io::Result<()> {
+        *self = serde_json::from_value(state)?;
+        Ok(())
+    }
+
+    fn signal(&self, route: Route) -> Option<Signal> {
+        if route.not_allowed_to_go().is_empty() {
+            Some(Signal::Green)
+        } else if !self.phases[self.phase].contains(&route) {
+            Some(Signal::Red)
+        } else if self.yellow {
+            Some(Signal::Yellow)
+        } else {
+            Some(Signal::Green)
+        }
+    }
+
+    fn summary(&self) -> Vec<(&'static str, String)> {
+        vec![("phases", format!("{:?}", self.phases))]
+    }
+
+    fn box_clone(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+fn conflicts(a: Route, b: Route) -> bool {
+    a.not_allowed_to_go().contains(&b) || b.not_allowed_to_go().contains(&a)
+}
+
+fn build_phases() -> Vec<Vec<Route>> {
+    let mut routes: Vec<Route> = Route::ALL.iter()
+        .copied()
+        .filter(|r| !r.not_allowed_to_go().is_empty())
+        .collect();
+    routes.sort_by_key(|r| std::cmp::Reverse(r.not_allowed_to_go().len()));
+    let mut phases: Vec<Vec<Route>> = vec![];
+    for route in routes {
+        match phases.iter_mut().find(|p| p.iter().all(|r| !conflicts(route, *r))) {
+            Some(phase) => phase.push(route),
+            None => phases.push(vec![route]),
+        }
+    }
+    phases
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::layout::IntersectionLayout;
+    use crate::vehicle::VehicleKind;
+    use crate::{Intersection, TIME_STEP};
+
+    // Runs the light tick by tick from `from` to `to` seconds with `cars` waiting to cross.
+    fn run(light: &mut TrafficLight, cars: &HashMap<u32, Car>, from: f32, to: f32) {
+        let layout = IntersectionLayout::default();
+        let mut tick = (from / TIME_STEP).round() as u32;
+        while (tick as f32) * TIME_STEP < to {
+            tick += 1;
+            light.tick(&Context { layout: &layout, time: tick as f32 * TIME_STEP, cars, leader: None });
+        }
+    }
+
+    fn waiting_on(routes: &[Route]) -> HashMap<u32, Car> {
+        let layout = IntersectionLayout::default();
+        routes.iter().enumerate().map(|(id, route)| {
+            let mut car = Car::new(layout.path(*route), VehicleKind::Car, 0, 0.0, id as u32, *route, 0.0);
+            car.distance = car.path.box_entry() - car.rectangle.0;
+            (id as u32, car)
+        }).collect()
+    }
+
+    #[test]
+    fn phases_do_not_conflict() {
+        let phases = build_phases();
+        assert_eq!(phases.len(), 4);
+        for phase in &phases {
+            assert!(phase.iter().all(|a| phase.iter().all(|b| !conflicts(*a, *b))));
+        }
+    }
+
+    #[test]
+    fn fixed_cycle() {
+        let mut light = TrafficLight::fixed();
+        // a car waiting on the last phase does not make a fixed light skip ahead
+        let cars = waiting_on(&[light.phases()[3][0]]);
+        let mut time = 0.0;
+        for cycle in 0..8 {
+            let phase = cycle % 4;
+            run(&mut light, &cars, time, time + GREEN_TIME - 0.5);
+            assert_eq!((light.phase, light.yellow), (phase, false), "cycle {}", cycle);
+            run(&mut light, &cars, time + GREEN_TIME - 0.5, time + GREEN_TIME + 0.5);
+            assert_eq!((light.phase, light.yellow), (phase, true), "cycle {}", cycle);
+            time += GREEN_TIME + YELLOW_TIME;
+            run(&mut light, &cars, time - YELLOW_TIME + 0.5, time + 0.5);
+            assert_eq!((light.phase, light.yellow), ((phase + 1) % 4, false), "cycle {}", cycle);
+        }
+    }
+
+    #[test]
+    fn actuated_extends_a_busy_green() {
+        let mut light = TrafficLight::actuated();
+        let cars = waiting_on(&[light.phases()[0][0], light.phases()[1][0]]);
+        run(&mut light, &cars, 0.0, GREEN_TIME * 2.0 - 0.5);
+        assert_eq!((light.phase, light.yellow), (0, false));
+        run(&mut light, &cars, GREEN_TIME * 2.0 - 0.5, GREEN_TIME * 2.0 + 0.5);
+        assert_eq!((light.phase, light.yellow), (0, true));
+    }
+
+    #[test]
+    fn actuated_keeps_green_while_nobody_else_waits() {
+        let mut light = TrafficLight::actuated();
+        run(&mut light, &HashMap::new(), 0.0, GREEN_TIME * 2.0 - 0.5);
+        assert_eq!((light.phase, light.yellow), (0, false));
+        // at the longest green it changes anyway, to the next phase as nobody waits
+        run(&mut light, &HashMap::new(), GREEN_TIME * 2.0 - 0.5, GREEN_TIME * 2.0 + YELLOW_TIME + 0.5);
+        assert_eq!((light.phase, light.yellow), (1, false));
+    }
+
+    #[test]
+    fn actuated_skips_empty_phases() {
+        let mut light = TrafficLight::actuated();
+        let cars = waiting_on(&[light.phases()[2][0]]);
+        // an empty green ends after the minimum
+        run(&mut light, &cars, 0.0, MIN_GREEN_TIME - 0.5);
+        assert_eq!((light.phase, light.yellow), (0, false));
+        run(&mut light, &cars, MIN_GREEN_TIME - 0.5, MIN_GREEN_TIME + 0.5);
+        assert_eq!((light.phase, light.yellow), (0, true));
+        // and phase 1 is skipped for the one with a car waiting
+        run(&mut light, &cars, MIN_GREEN_TIME + 0.5, MIN_GREEN_TIME + YELLOW_TIME + 0.5);
+        assert_eq!((light.phase, light.yellow), (2, false));
+    }
+
+    #[test]
+    fn keeps_cycling_while_the_intersection_is_empty() {
+        let mut intersection = Intersection::with_seed(1);
+        intersection.set_controller(Box::new(TrafficLight::fixed()));
+        // 108 s is three full cycles and 4 s into the second phase
+        for _ in 0..(108.0 / TIME_STEP) as u32 {
+            intersection.step(TIME_STEP);
+        }
+        let phases = build_phases();
+        assert_eq!(intersection.controller().signal(phases[0][0]), Some(Signal::Red));
+        assert_eq!(intersection.controller().signal(phases[1][0]), Some(Signal::Green));
+    }
+}
@@ -0,0 +1,111 @@
+use std::io;
+
+use r::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::layout::read_config;
+use crate::{CAR_ACCELERATION, CAR_DECELERATION, CAR_HEIGHT, CAR_LENGTH, CAR_SPEED_FAST};
+
+/// What kind of vehicle a `Car` is. Apart from plain cars, kinds are bigger or slower,
+/// so they take longer to clear the crossing.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum VehicleKind {
+    #[default]
+    Car,
+    Truck,
+    Bus,
+    Emergency,
+}
+
+impl VehicleKind {
+    pub const ALL: [VehicleKind; 4] = [VehicleKind::Car, VehicleKind::Truck, VehicleKind::Bus, VehicleKind::Emergency];
+
+    /// Length and width in pixels.
+    pub fn size(&self) -> (f32, f32) {
+        match self {
+            VehicleKind::Car => (CAR_LENGTH, CAR_HEIGHT),
+            VehicleKind::Truck => (70.0, 14.0),
+            VehicleKind::Bus => (55.0, 14.0),
+            VehicleKind::Emergency => (34.0, 12.0),
+        }
+    }
+
+    /// Widest vehicle of any kind, which every lane has to fit.
+    pub fn max_width() -> f32 {
+        VehicleKind::ALL.iter().map(|k| k.size().1).fold(0.0, f32::max)
+    }
+
+    /// Top speed in pixels per second; controllers' target speeds are capped to it.
+    pub fn max_speed(&self) -> f32 {
+        match self {
+            VehicleKind::Car | VehicleKind::Emergency => CAR_SPEED_FAST,
+            VehicleKind::Truck => 110.0,
+            VehicleKind::Bus => 140.0,
+        }
+    }
+
+    /// Acceleration and deceleration in pixels per second squared.
+    pub fn acceleration(&self) -> (f32, f32) {
+        match self {
+            VehicleKind::Car => (CAR_ACCELERATION, CAR_DECELERATION),
+            VehicleKind::Truck => (50.0, 150.0),
+            VehicleKind::Bus => (70.0, 180.0),
+            VehicleKind::Emergency => (250.0, 350.0),
+        }
+    }
+}
+
+/// Share of each kind among spawned vehicles, relative to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VehicleMix {
+    pub car: f32,
+    pub truck: f32,
+    pub bus: f32,
+    pub emergency: f32,
+}
+
+impl Default for VehicleMix {
+    fn default() -> Self {
+        VehicleMix { car: 1.0, truck: 0.0, bus: 0.0, emergency: 0.0 }
+    }
+}
+
+impl VehicleMix {
+    /// Loads a mix whose shares are not negative and not all 0.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> io::Result<VehicleMix> {
+        let mix: VehicleMix = read_config(path)?;
+        let shares = mix.shares();
+        if shares.iter().any(|(_, share)| *share < 0.0) || shares.iter().all(|(_, share)| *share == 0.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "shares must not be negative and not all 0"));
+        }
+        Ok(mix)
+    }
+
+    fn shares(&self) -> [(VehicleKind, f32); 4] {
+        [
+            (VehicleKind::Car, self.car),
+            (VehicleKind::Truck, self.truck),
+            (VehicleKind::Bus, self.bus),
+            (VehicleKind::Emergency, self.emergency),
+        ]
+    }
+
+    /// Picks a kind with probability proportional to its share. A mix of a single kind
+    /// draws nothing from `rng`, so all-car runs stay the same as before kinds existed.
+    pub fn pick<R: Rng>(&self, rng: &mut R) -> VehicleKind {
+        let shares: Vec<_> = self.shares().into_iter().filter(|(_, share)| *share > 0.0).collect();
+        if shares.len() <= 1 {
+            return shares.first().map_or(VehicleKind::Car, |(kind, _)| *kind);
+        }
+        let total: f32 = shares.iter().map(|(_, share)| share).sum();
+        let mut x = rng.gen_range(0.0..total);
+        for (kind, share) in &shares {
+            if x < *share {
+                return *kind;
+            }
+            x -= share;
+        }
+        shares[shares.len() - 1].0
+    }
+}